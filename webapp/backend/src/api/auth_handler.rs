@@ -1,17 +1,13 @@
+use crate::api::extractors::{ValidatedJson, ValidatedQuery};
 use crate::domains::auth_service::AuthService;
 use crate::domains::dto::auth::{LoginRequestDto, LogoutRequestDto, RegisterRequestDto};
 use crate::errors::AppError;
 use crate::repositories::auth_repository::AuthRepositoryImpl;
 use actix_web::{web, HttpResponse};
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-use std::fs::File;
-use std::io::Write;
-use std::path::Path;
-
-use std::time::Instant;
-
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct ValidateSessionQueryParams {
     session_token: Option<String>,
 }
@@ -23,7 +19,7 @@ pub struct ValidationResponse {
 
 pub async fn validate_session_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
-    query: web::Query<ValidateSessionQueryParams>,
+    query: ValidatedQuery<ValidateSessionQueryParams>,
 ) -> Result<HttpResponse, AppError> {
     match &query.session_token {
         Some(session_token) => match service.validate_session(session_token.as_str()).await {
@@ -36,7 +32,7 @@ pub async fn validate_session_handler(
 
 pub async fn register_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
-    req: web::Json<RegisterRequestDto>,
+    req: ValidatedJson<RegisterRequestDto>,
 ) -> Result<HttpResponse, AppError> {
     match service
         .register_user(&req.username, &req.password, &req.role, req.area_id)
@@ -49,27 +45,17 @@ pub async fn register_handler(
 
 pub async fn login_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
-    req: web::Json<LoginRequestDto>,
+    req: ValidatedJson<LoginRequestDto>,
 ) -> Result<HttpResponse, AppError> {
-    //println!("hello,world!");
-    let start = Instant::now();
-
     match service.login_user(&req.username, &req.password).await {
-        Ok(response) => {
-            // 打印计算结果和运行时间
-            let duration = start.elapsed();
-            //println!("login_handler 时间间隔: {:?}", duration);
-            Ok(HttpResponse::Ok().json(response))
-        },
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
         Err(err) => Err(err),
     }
-
-
 }
 
 pub async fn logout_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
-    req: web::Json<LogoutRequestDto>,
+    req: ValidatedJson<LogoutRequestDto>,
 ) -> Result<HttpResponse, AppError> {
     match service.logout_user(&req.session_token).await {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
@@ -77,44 +63,29 @@ pub async fn logout_handler(
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct UserProfileImageQueryParams {
+    #[validate(range(min = 1, max = 2000))]
     w: Option<i32>,
+    #[validate(range(min = 1, max = 2000))]
     h: Option<i32>,
 }
 
-
-
 pub async fn user_profile_image_handler(
     service: web::Data<AuthService<AuthRepositoryImpl>>,
     path: web::Path<i32>,
-    query: web::Query<UserProfileImageQueryParams>,
+    query: ValidatedQuery<UserProfileImageQueryParams>,
 ) -> Result<HttpResponse, AppError> {
-
-    //println!("user_profile_image_handler");
-    let image_start = Instant::now();
     let user_id = path.into_inner();
     let width = query.w.unwrap_or(500);
     let height = query.h.unwrap_or(500);
-    //println!("user_profile_image_handler");
 
-    
     let profile_image_byte = service
         .get_resized_profile_image_byte(user_id, width, height)
         .await?;
 
-    // 打印计算结果和运行时间
-    let image_duration = image_start.elapsed();
-    //println!("user_profile_image_handler 时间间隔: {:?}", image_duration);
-
     Ok(HttpResponse::Ok()
         .content_type("image/png")
         .append_header(("Cache-Control", "max-age=3600"))
         .body(profile_image_byte))
-    
-
-
-
-    
-
 }
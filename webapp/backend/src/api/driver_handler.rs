@@ -0,0 +1,48 @@
+use crate::api::extractors::ValidatedJson;
+use crate::domains::dto::driver::UpdateJobStatusRequestDto;
+use crate::domains::order_service::OrderService;
+use crate::errors::{AppError, ErrorCode};
+use crate::middlewares::auth_middleware::AuthenticatedUser;
+use crate::repositories::auth_repository::AuthRepositoryImpl;
+use crate::repositories::map_repository::MapRepositoryImpl;
+use crate::repositories::order_repository::OrderRepositoryImpl;
+use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
+use actix_web::{web, HttpResponse};
+
+type OrderServiceImpl = OrderService<
+    OrderRepositoryImpl,
+    TowTruckRepositoryImpl,
+    AuthRepositoryImpl,
+    MapRepositoryImpl,
+>;
+
+fn require_driver(user: &AuthenticatedUser) -> Result<(), AppError> {
+    if user.role != "driver" {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    Ok(())
+}
+
+pub async fn get_jobs_handler(
+    service: web::Data<OrderServiceImpl>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    require_driver(&user)?;
+    let jobs = service.get_driver_jobs(user.user_id).await?;
+
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+pub async fn update_job_status_handler(
+    service: web::Data<OrderServiceImpl>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: ValidatedJson<UpdateJobStatusRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    require_driver(&user)?;
+    service
+        .advance_job(user.user_id, path.into_inner(), &req.status)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
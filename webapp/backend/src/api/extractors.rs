@@ -0,0 +1,122 @@
+use std::ops::Deref;
+
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+use crate::errors::{AppError, FieldError};
+
+// ハンドラが呼ばれる前にデシリアライズとバリデーションを行う web::Json / web::Query の代替
+pub struct ValidatedJson<T>(pub T);
+
+pub struct ValidatedQuery<T>(pub T);
+
+impl<T> Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Deref for ValidatedQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedJson<T> {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let value = json
+                .await
+                .map_err(|e| AppError::Validation(vec![FieldError::new("body", e.to_string())]))?
+                .into_inner();
+            value.validate().map_err(to_app_error)?;
+            Ok(ValidatedJson(value))
+        })
+    }
+}
+
+impl<T: DeserializeOwned + Validate + 'static> FromRequest for ValidatedQuery<T> {
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let result = web::Query::<T>::from_query(req.query_string())
+            .map_err(|e| AppError::Validation(vec![FieldError::new("query", e.to_string())]))
+            .and_then(|query| {
+                let value = query.into_inner();
+                value.validate().map_err(to_app_error)?;
+                Ok(ValidatedQuery(value))
+            });
+
+        Box::pin(async move { result })
+    }
+}
+
+fn to_app_error(errors: ValidationErrors) -> AppError {
+    let mut field_errors = Vec::new();
+    collect_field_errors("", &errors, &mut field_errors);
+    field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+    AppError::Validation(field_errors)
+}
+
+fn collect_field_errors(prefix: &str, errors: &ValidationErrors, out: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                for error in errors {
+                    let message = match &error.message {
+                        Some(message) => message.to_string(),
+                        None => describe(error),
+                    };
+                    out.push(FieldError::new(&path, message));
+                }
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(&path, errors, out),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(&format!("{}[{}]", path, index), errors, out);
+                }
+            }
+        }
+    }
+}
+
+// メッセージ未指定の組み込みバリデーションは、パラメータから文言を組み立てる
+fn describe(error: &validator::ValidationError) -> String {
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+
+    match error.code.as_ref() {
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("{} 以上 {} 以下の値を指定してください", min, max),
+            (Some(min), None) => format!("{} 以上の値を指定してください", min),
+            (None, Some(max)) => format!("{} 以下の値を指定してください", max),
+            (None, None) => "範囲外の値です".to_string(),
+        },
+        "length" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => {
+                format!("{} 文字以上 {} 文字以下で指定してください", min, max)
+            }
+            (Some(min), None) => format!("{} 文字以上で指定してください", min),
+            (None, Some(max)) => format!("{} 文字以下で指定してください", max),
+            (None, None) => "長さが不正です".to_string(),
+        },
+        code => format!("不正な値です ({})", code),
+    }
+}
@@ -1,14 +1,122 @@
+use crate::domains::map_service::{area_graph_stats, MapService};
 use crate::errors::AppError;
-use actix_web::HttpResponse;
+use crate::infrastructure::db::{pool_stats, DbConfig};
+use crate::repositories::map_repository::MapRepositoryImpl;
+use actix_web::{web, HttpResponse};
 use serde::Serialize;
+use sqlx::MySqlPool;
+use std::path::Path;
+
+const PROFILE_IMAGE_DIR: &str = "images/user_profile";
 
 #[derive(Serialize)]
 struct HealthCheckResponse {
     status: String,
 }
 
+#[derive(Serialize)]
+struct DependencyCheck {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl DependencyCheck {
+    fn ok() -> Self {
+        DependencyCheck {
+            status: "ok",
+            detail: None,
+        }
+    }
+
+    fn failed(detail: String) -> Self {
+        DependencyCheck {
+            status: "failed",
+            detail: Some(detail),
+        }
+    }
+
+    fn is_failed(&self) -> bool {
+        self.status == "failed"
+    }
+}
+
+#[derive(Serialize)]
+struct ReadinessChecks {
+    database: DependencyCheck,
+    images: DependencyCheck,
+    node_index: DependencyCheck,
+    graph_cache: DependencyCheck,
+}
+
+#[derive(Serialize)]
+struct ReadinessResponse {
+    status: &'static str,
+    checks: ReadinessChecks,
+}
+
 pub async fn health_check_handler() -> Result<HttpResponse, AppError> {
     Ok(HttpResponse::Ok().json(HealthCheckResponse {
         status: "OK".to_string(),
     }))
 }
+
+pub async fn liveness_handler() -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(HealthCheckResponse {
+        status: "OK".to_string(),
+    }))
+}
+
+pub async fn readiness_handler(
+    pool: web::Data<MySqlPool>,
+    map_service: web::Data<MapService<MapRepositoryImpl>>,
+) -> Result<HttpResponse, AppError> {
+    let database = match sqlx::query("SELECT 1").execute(pool.get_ref()).await {
+        Ok(_) => DependencyCheck::ok(),
+        Err(err) => DependencyCheck::failed(err.to_string()),
+    };
+
+    let images = if Path::new(PROFILE_IMAGE_DIR).is_dir() {
+        DependencyCheck::ok()
+    } else {
+        DependencyCheck::failed(format!("{} が見つかりません", PROFILE_IMAGE_DIR))
+    };
+
+    // ノードのインデックスと道路グラフは初回の利用時にエリアごとに作る。未作成でも処理は続けられる
+    let (areas, nodes) = map_service.node_index_stats();
+    let node_index = DependencyCheck {
+        status: if areas == 0 { "empty" } else { "ok" },
+        detail: Some(format!("{} インデックス, {} ノード", areas, nodes)),
+    };
+    let (areas, nodes) = area_graph_stats();
+    let graph_cache = DependencyCheck {
+        status: if areas == 0 { "empty" } else { "ok" },
+        detail: Some(format!("{} エリア, {} ノード", areas, nodes)),
+    };
+
+    let checks = ReadinessChecks {
+        database,
+        images,
+        node_index,
+        graph_cache,
+    };
+
+    if checks.database.is_failed() || checks.images.is_failed() {
+        return Ok(HttpResponse::ServiceUnavailable().json(ReadinessResponse {
+            status: "degraded",
+            checks,
+        }));
+    }
+
+    Ok(HttpResponse::Ok().json(ReadinessResponse {
+        status: "OK",
+        checks,
+    }))
+}
+
+pub async fn pool_stats_handler(
+    pool: web::Data<MySqlPool>,
+    config: web::Data<DbConfig>,
+) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(pool_stats(&pool, &config)))
+}
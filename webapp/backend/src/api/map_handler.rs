@@ -1,19 +1,52 @@
 use crate::{
-    domains::{dto::map::UpdateEdgeRequestDto, map_service::MapService},
+    api::extractors::{ValidatedJson, ValidatedQuery},
+    domains::{
+        dto::map::{NearestNodeQuery, UpdateEdgeRequestDto},
+        map_service::MapService,
+        order_service::OrderService,
+    },
     errors::AppError,
-    repositories::map_repository::MapRepositoryImpl,
+    repositories::{
+        auth_repository::AuthRepositoryImpl, map_repository::MapRepositoryImpl,
+        order_repository::OrderRepositoryImpl, tow_truck_repository::TowTruckRepositoryImpl,
+    },
 };
 use actix_web::{web, HttpResponse};
+use tracing::warn;
 
 pub async fn update_edge_handler(
     service: web::Data<MapService<MapRepositoryImpl>>,
-    req: web::Json<UpdateEdgeRequestDto>,
+    order_service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    req: ValidatedJson<UpdateEdgeRequestDto>,
 ) -> Result<HttpResponse, AppError> {
-    match service
+    service
         .update_edge(req.node_a_id, req.node_b_id, req.weight)
-        .await
-    {
-        Ok(_) => Ok(HttpResponse::Ok().finish()),
-        Err(err) => Err(err),
-    }
+        .await?;
+
+    // 到着見込みの配信はレスポンスを待たせないよう裏で行う
+    // 失敗しても、辺の更新自体は成功として返す
+    let node_id = req.node_a_id;
+    actix_web::rt::spawn(async move {
+        if let Err(err) = order_service.refresh_etas_for_node(node_id).await {
+            warn!(error = ?err, "到着見込みの更新に失敗しました");
+        }
+    });
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn nearest_node_handler(
+    service: web::Data<MapService<MapRepositoryImpl>>,
+    query: ValidatedQuery<NearestNodeQuery>,
+) -> Result<HttpResponse, AppError> {
+    let node = service.snap_to_node(query.area, query.x, query.y).await?;
+
+    Ok(HttpResponse::Ok().json(node))
 }
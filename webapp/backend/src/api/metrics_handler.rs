@@ -0,0 +1,13 @@
+use crate::errors::AppError;
+use crate::infrastructure::{db::DbConfig, metrics::metrics};
+use actix_web::{web, HttpResponse};
+use sqlx::MySqlPool;
+
+pub async fn metrics_handler(
+    pool: web::Data<MySqlPool>,
+    config: web::Data<DbConfig>,
+) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics().render(&pool, &config)))
+}
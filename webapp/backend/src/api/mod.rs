@@ -1,5 +1,9 @@
 pub mod auth_handler;
+pub mod driver_handler;
+pub mod extractors;
 pub mod health_check_handler;
 pub mod map_handler;
+pub mod metrics_handler;
 pub mod order_handler;
+pub mod stream_handler;
 pub mod tow_truck_handler;
@@ -1,14 +1,30 @@
+use crate::api::extractors::{ValidatedJson, ValidatedQuery};
+use crate::domains::auth_service::AuthService;
+use crate::domains::auto_dispatch::AutoDispatchControl;
+use crate::domains::dispatch_offer;
 use crate::domains::dto::order::{
-    ClientOrderRequestDto, DispatcherOrderRequestDto, UpdateOrderStatusRequestDto,
+    AcceptAssignmentPlanRequestDto, CancelOrderRequestDto, ClientOrderRequestDto,
+    DispatcherOrderRequestDto, HoldOrderRequestDto, ReassignOrderRequestDto,
+    UnassignOrderRequestDto, UpdateAutoDispatchRequestDto, UpdateKillSwitchRequestDto,
+    UpdateOrderStatusRequestDto,
 };
+use crate::domains::dto::validators::{
+    validate_order_sort_key, validate_order_status, validate_sort_order,
+};
+use crate::domains::map_service::MapService;
 use crate::domains::order_service::OrderService;
-use crate::errors::AppError;
+use crate::domains::tow_truck_service::TowTruckService;
+use crate::errors::{AppError, ErrorCode};
+use crate::middlewares::auth_middleware::AuthenticatedUser;
+use crate::models::user::Dispatcher;
 use crate::repositories::auth_repository::AuthRepositoryImpl;
 use crate::repositories::map_repository::MapRepositoryImpl;
 use crate::repositories::order_repository::OrderRepositoryImpl;
 use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
 use actix_web::{web, HttpResponse};
 use serde::Deserialize;
+use tracing::{info, warn};
+use validator::Validate;
 
 pub async fn update_order_status_handler(
     service: web::Data<
@@ -19,7 +35,7 @@ pub async fn update_order_status_handler(
             MapRepositoryImpl,
         >,
     >,
-    req: web::Json<UpdateOrderStatusRequestDto>,
+    req: ValidatedJson<UpdateOrderStatusRequestDto>,
 ) -> Result<HttpResponse, AppError> {
     match service.update_order_status(req.order_id, &req.status).await {
         Ok(_) => Ok(HttpResponse::Ok().finish()),
@@ -44,13 +60,19 @@ pub async fn get_order_handler(
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct PaginatedOrderQuery {
+    #[validate(range(min = 0, max = 100000))]
     page: Option<i32>,
+    #[validate(range(min = 1, max = 1000))]
     page_size: Option<i32>,
+    #[validate(custom(function = "validate_order_sort_key"))]
     sort_by: Option<String>,
+    #[validate(custom(function = "validate_sort_order"))]
     sort_order: Option<String>,
+    #[validate(custom(function = "validate_order_status"))]
     status: Option<String>,
+    #[validate(range(min = 1))]
     area: Option<i32>,
 }
 
@@ -63,7 +85,7 @@ pub async fn get_paginated_orders_handler(
             MapRepositoryImpl,
         >,
     >,
-    query: web::Query<PaginatedOrderQuery>,
+    query: ValidatedQuery<PaginatedOrderQuery>,
 ) -> Result<HttpResponse, AppError> {
     match service
         .get_paginated_orders(
@@ -90,10 +112,18 @@ pub async fn create_client_order_handler(
             MapRepositoryImpl,
         >,
     >,
-    req: web::Json<ClientOrderRequestDto>,
+    map_service: web::Data<MapService<MapRepositoryImpl>>,
+    req: ValidatedJson<ClientOrderRequestDto>,
 ) -> Result<HttpResponse, AppError> {
+    let node_id = match (req.node_id, req.x, req.y) {
+        (Some(node_id), _, _) => node_id,
+        (None, Some(x), Some(y)) => map_service.snap_to_node(req.area_id, x, y).await?.node_id,
+        // ValidatedJson で弾かれるので到達しない
+        _ => return Err(AppError::BadRequest),
+    };
+
     match service
-        .create_client_order(req.client_id, req.node_id, req.car_value)
+        .create_client_order(req.client_id, node_id, req.car_value)
         .await
     {
         Ok(_) => Ok(HttpResponse::Created().finish()),
@@ -110,7 +140,7 @@ pub async fn create_dispatcher_order_handler(
             MapRepositoryImpl,
         >,
     >,
-    req: web::Json<DispatcherOrderRequestDto>,
+    req: ValidatedJson<DispatcherOrderRequestDto>,
 ) -> Result<HttpResponse, AppError> {
     match service
         .create_dispatcher_order(
@@ -125,3 +155,317 @@ pub async fn create_dispatcher_order_handler(
         Err(err) => Err(err),
     }
 }
+
+// 配車担当者本人のエリアだけを扱う
+async fn current_dispatcher(
+    auth_service: &AuthService<AuthRepositoryImpl>,
+    user: &AuthenticatedUser,
+) -> Result<Dispatcher, AppError> {
+    if user.role != "dispatcher" {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    auth_service
+        .find_dispatcher_by_user_id(user.user_id)
+        .await?
+        .ok_or(AppError::Coded(ErrorCode::DispatcherNotFound))
+}
+
+pub async fn get_assignment_plan_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    let plan = service.create_assignment_plan(dispatcher.area_id).await?;
+
+    Ok(HttpResponse::Ok().json(plan))
+}
+
+pub async fn accept_assignment_plan_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: AuthenticatedUser,
+    req: ValidatedJson<AcceptAssignmentPlanRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    let assignments: Vec<(i32, i32)> = req
+        .assignments
+        .iter()
+        .map(|assignment| (assignment.order_id, assignment.tow_truck_id))
+        .collect();
+    service
+        .accept_assignment_plan(&dispatcher, &assignments)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn auto_dispatch_status(
+    service: &OrderService<
+        OrderRepositoryImpl,
+        TowTruckRepositoryImpl,
+        AuthRepositoryImpl,
+        MapRepositoryImpl,
+    >,
+    control: &AutoDispatchControl,
+    area_id: i32,
+) -> Result<HttpResponse, AppError> {
+    let pending_order_ids: Vec<i32> = service
+        .get_pending_orders(area_id)
+        .await?
+        .iter()
+        .map(|order| order.id)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(control.status(area_id, &pending_order_ids)))
+}
+
+pub async fn get_auto_dispatch_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    control: web::Data<AutoDispatchControl>,
+    user: AuthenticatedUser,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    auto_dispatch_status(&service, &control, dispatcher.area_id).await
+}
+
+// 配車担当者は自分のエリアの自動配車だけを切り替えられる
+pub async fn update_auto_dispatch_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    control: web::Data<AutoDispatchControl>,
+    user: AuthenticatedUser,
+    req: ValidatedJson<UpdateAutoDispatchRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    control.set_enabled(dispatcher.area_id, req.enabled);
+    info!(
+        area_id = dispatcher.area_id,
+        dispatcher_id = dispatcher.id,
+        enabled = req.enabled,
+        "自動配車の設定を変更しました"
+    );
+
+    auto_dispatch_status(&service, &control, dispatcher.area_id).await
+}
+
+// 緊急停止。配車担当者は自分のエリアの自動配車だけを止められる
+pub async fn update_kill_switch_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    control: web::Data<AutoDispatchControl>,
+    user: AuthenticatedUser,
+    req: ValidatedJson<UpdateKillSwitchRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    control.set_kill_switch(dispatcher.area_id, req.engaged);
+    warn!(
+        area_id = dispatcher.area_id,
+        dispatcher_id = dispatcher.id,
+        engaged = req.engaged,
+        "自動配車の緊急停止を切り替えました"
+    );
+
+    auto_dispatch_status(&service, &control, dispatcher.area_id).await
+}
+
+pub async fn hold_order_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    control: web::Data<AutoDispatchControl>,
+    user: AuthenticatedUser,
+    req: ValidatedJson<HoldOrderRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    let order = service.get_order_by_id(req.order_id).await?;
+    if order.area_id != dispatcher.area_id {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    control.set_held(order.id, order.area_id, req.held);
+
+    auto_dispatch_status(&service, &control, dispatcher.area_id).await
+}
+
+pub async fn cancel_order_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: ValidatedJson<CancelOrderRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let cancellation = service
+        .cancel_order(
+            path.into_inner(),
+            user.user_id,
+            &user.role,
+            &req.reason,
+            req.note.clone(),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(cancellation))
+}
+
+pub async fn reassign_order_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: ValidatedJson<ReassignOrderRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    let assignments = service
+        .change_assignment(
+            &dispatcher,
+            path.into_inner(),
+            Some(req.tow_truck_id),
+            &req.reason,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(assignments))
+}
+
+pub async fn unassign_order_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+    req: ValidatedJson<UnassignOrderRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let dispatcher = current_dispatcher(&auth_service, &user).await?;
+    let assignments = service
+        .change_assignment(&dispatcher, path.into_inner(), None, &req.reason)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(assignments))
+}
+
+pub async fn get_order_assignments_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    let assignments = service.get_order_assignments(path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(assignments))
+}
+
+pub async fn accept_offer_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    if user.role != "driver" {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    service
+        .respond_to_offer(user.user_id, path.into_inner(), true)
+        .await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+// 辞退はすぐに記録し、次のレッカー車への付け替えに失敗した場合は期限切れの確認で再試行する
+pub async fn decline_offer_handler(
+    service: web::Data<
+        OrderService<
+            OrderRepositoryImpl,
+            TowTruckRepositoryImpl,
+            AuthRepositoryImpl,
+            MapRepositoryImpl,
+        >,
+    >,
+    tow_truck_service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    user: AuthenticatedUser,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, AppError> {
+    if user.role != "driver" {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    let order_id = path.into_inner();
+    let tow_truck_id = service
+        .respond_to_offer(user.user_id, order_id, false)
+        .await?;
+
+    if let Err(err) =
+        dispatch_offer::fall_back(&tow_truck_service, &service, order_id, tow_truck_id).await
+    {
+        warn!(order_id, error = ?err, "辞退された申し出の付け替えに失敗しました");
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
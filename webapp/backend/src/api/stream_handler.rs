@@ -0,0 +1,163 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use actix_web::{web, web::Bytes, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures_util::{future::ready, stream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, Instant};
+use tracing::warn;
+use validator::Validate;
+
+use crate::api::extractors::ValidatedQuery;
+use crate::domains::auth_service::AuthService;
+use crate::errors::{AppError, ErrorCode};
+use crate::infrastructure::events::{order_event_hub, tow_truck_event_hub, Replay};
+use crate::middlewares::auth_middleware::AuthenticatedUser;
+use crate::repositories::auth_repository::AuthRepositoryImpl;
+
+// プロキシやロードバランサにアイドル接続として切られないよう、定期的にコメント行を送る
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const RETRY_MILLIS: u64 = 3000;
+// クライアントは受け取ったら一覧を取り直す
+const RESET_EVENT: &str = "event: reset\ndata: {}\n\n";
+const RESET_MESSAGE: &str = r#"{"event":"reset","data":{}}"#;
+// この間 Pong を含めて何も届かない WebSocket は切断されたとみなす
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+
+pub async fn tow_truck_events_handler(
+    auth_service: web::Data<AuthService<AuthRepositoryImpl>>,
+    user: AuthenticatedUser,
+    req: HttpRequest,
+) -> Result<HttpResponse, AppError> {
+    if user.role != "dispatcher" {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+    let dispatcher = auth_service
+        .find_dispatcher_by_user_id(user.user_id)
+        .await?
+        .ok_or(AppError::Coded(ErrorCode::DispatcherNotFound))?;
+
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok());
+    let (replay, receiver) = tow_truck_event_hub().subscribe(dispatcher.area_id, last_event_id);
+
+    let mut initial = format!("retry: {}\n\n", RETRY_MILLIS);
+    match replay {
+        Replay::Events(events) => {
+            for event in events {
+                initial.push_str(&event.to_sse());
+            }
+        }
+        Replay::Reset => initial.push_str(RESET_EVENT),
+    }
+
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((event.to_sse(), receiver)),
+            // 受信が追いつかずに捨てられたイベントがある
+            Err(RecvError::Lagged(_)) => Some((RESET_EVENT.to_string(), receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    });
+    let heartbeat = stream::unfold(
+        interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL),
+        |mut interval| async move {
+            interval.tick().await;
+            Some((": heartbeat\n\n".to_string(), interval))
+        },
+    );
+
+    let body = stream::once(ready(initial))
+        .chain(stream::select(events, heartbeat))
+        .map(|chunk| Ok::<_, Infallible>(Bytes::from(chunk)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct OrderEventsQuery {
+    // 再接続時に最後に受け取ったイベントの id
+    #[validate(length(max = 64))]
+    last_event_id: Option<String>,
+}
+
+// 依頼者が自分の依頼の状態変化を、ドライバーが割り当ての申し出を受け取る WebSocket
+pub async fn order_events_ws_handler(
+    user: AuthenticatedUser,
+    query: ValidatedQuery<OrderEventsQuery>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> Result<HttpResponse, AppError> {
+    if !matches!(user.role.as_str(), "client" | "driver") {
+        return Err(AppError::Coded(ErrorCode::Forbidden));
+    }
+
+    let (response, mut session, mut messages) =
+        actix_ws::handle(&req, body).map_err(|_| AppError::BadRequest)?;
+    let (replay, mut receiver) =
+        order_event_hub().subscribe(user.user_id, query.last_event_id.as_deref());
+
+    actix_web::rt::spawn(async move {
+        let initial = match replay {
+            Replay::Events(events) => events.iter().map(|event| event.to_json()).collect(),
+            Replay::Reset => vec![RESET_MESSAGE.to_string()],
+        };
+        for message in initial {
+            if session.text(message).await.is_err() {
+                return;
+            }
+        }
+
+        let mut heartbeat = interval_at(Instant::now() + HEARTBEAT_INTERVAL, HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+
+        loop {
+            tokio::select! {
+                event = receiver.recv() => {
+                    let message = match event {
+                        Ok(event) => event.to_json(),
+                        Err(RecvError::Lagged(_)) => RESET_MESSAGE.to_string(),
+                        Err(RecvError::Closed) => break,
+                    };
+                    if session.text(message).await.is_err() {
+                        break;
+                    }
+                }
+                message = messages.next() => {
+                    match message {
+                        Some(Ok(Message::Ping(bytes))) => {
+                            last_seen = Instant::now();
+                            if session.pong(&bytes).await.is_err() {
+                                break;
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        // クライアントからのメッセージは生存確認としてだけ扱う
+                        Some(Ok(_)) => last_seen = Instant::now(),
+                        Some(Err(err)) => {
+                            warn!(error = ?err, "WebSocket の受信に失敗しました");
+                            break;
+                        }
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > CLIENT_TIMEOUT || session.ping(b"").await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
@@ -1,19 +1,52 @@
+use crate::api::extractors::{ValidatedJson, ValidatedQuery};
+use crate::domains::dto::validators::{validate_page_size_or_all, validate_tow_truck_status};
+use crate::domains::map_service::MapService;
+use crate::domains::order_service::OrderService;
 use crate::domains::tow_truck_service::TowTruckService;
-use crate::errors::AppError;
+use crate::errors::{AppError, ErrorCode};
+use crate::models::location::NewLocation;
+use crate::repositories::auth_repository::AuthRepositoryImpl;
 use crate::repositories::order_repository::OrderRepositoryImpl;
 use crate::repositories::tow_truck_repository::TowTruckRepositoryImpl;
 use crate::{
-    domains::dto::tow_truck::UpdateLocationRequestDto,
+    domains::dto::tow_truck::{BatchUpdateLocationRequestDto, UpdateLocationRequestDto},
     repositories::map_repository::MapRepositoryImpl,
 };
 use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use tracing::warn;
+use validator::{Validate, ValidationError};
 
-#[derive(Deserialize, Debug)]
+type OrderServiceImpl = OrderService<
+    OrderRepositoryImpl,
+    TowTruckRepositoryImpl,
+    AuthRepositoryImpl,
+    MapRepositoryImpl,
+>;
+
+// 到着見込みの配信はレスポンスを待たせないよう裏で行う
+// 失敗しても、位置情報の更新自体は成功として返す
+fn refresh_etas(order_service: web::Data<OrderServiceImpl>, tow_truck_ids: Vec<i32>) {
+    actix_web::rt::spawn(async move {
+        if let Err(err) = order_service
+            .refresh_etas_for_tow_trucks(&tow_truck_ids)
+            .await
+        {
+            warn!(error = ?err, "到着見込みの更新に失敗しました");
+        }
+    });
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct PaginatedTowTruckQuery {
+    #[validate(range(min = 0, max = 100000))]
     page: Option<i32>,
+    #[validate(custom(function = "validate_page_size_or_all"))]
     page_size: Option<i32>,
+    #[validate(custom(function = "validate_tow_truck_status"))]
     status: Option<String>,
+    #[validate(range(min = 1))]
     area: Option<i32>,
 }
 
@@ -21,7 +54,7 @@ pub async fn get_paginated_tow_trucks_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
-    query: web::Query<PaginatedTowTruckQuery>,
+    query: ValidatedQuery<PaginatedTowTruckQuery>,
 ) -> Result<HttpResponse, AppError> {
     let tow_trucks = service
         .get_all_tow_trucks(
@@ -44,7 +77,7 @@ pub async fn get_tow_truck_handler(
     let id = path.into_inner();
     match service.get_tow_truck_by_id(id).await {
         Ok(Some(tow_truck)) => Ok(HttpResponse::Ok().json(tow_truck)),
-        Ok(None) => Ok(HttpResponse::NotFound().finish()),
+        Ok(None) => Err(AppError::Coded(ErrorCode::TowTruckNotFound)),
         Err(err) => Err(err),
     }
 }
@@ -53,16 +86,79 @@ pub async fn update_location_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
-    req: web::Json<UpdateLocationRequestDto>,
+    map_service: web::Data<MapService<MapRepositoryImpl>>,
+    order_service: web::Data<OrderServiceImpl>,
+    req: ValidatedJson<UpdateLocationRequestDto>,
 ) -> Result<HttpResponse, AppError> {
-    service
-        .update_location(req.tow_truck_id, req.node_id)
-        .await?;
+    let node_id = match (req.node_id, req.x, req.y) {
+        (Some(node_id), _, _) => node_id,
+        (None, Some(x), Some(y)) => {
+            let tow_truck = service
+                .get_tow_truck_by_id(req.tow_truck_id)
+                .await?
+                .ok_or(AppError::Coded(ErrorCode::TowTruckNotFound))?;
+            map_service
+                .snap_to_node(Some(tow_truck.area_id), x, y)
+                .await?
+                .node_id
+        }
+        // ValidatedJson で弾かれるので到達しない
+        _ => return Err(AppError::BadRequest),
+    };
+
+    service.update_location(req.tow_truck_id, node_id).await?;
+    refresh_etas(order_service, vec![req.tow_truck_id]);
+
     Ok(HttpResponse::Ok().finish())
 }
 
-#[derive(Deserialize, Debug)]
+pub async fn batch_update_location_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    order_service: web::Data<OrderServiceImpl>,
+    req: ValidatedJson<BatchUpdateLocationRequestDto>,
+) -> Result<HttpResponse, AppError> {
+    let records = req
+        .locations
+        .iter()
+        .map(|record| NewLocation {
+            tow_truck_id: record.tow_truck_id,
+            node_id: record.node_id,
+            timestamp: record.timestamp,
+            flagged: false,
+        })
+        .collect();
+    let result = service.update_locations_batch(records).await?;
+
+    let mut tow_truck_ids: Vec<i32> = req.locations.iter().map(|r| r.tow_truck_id).collect();
+    tow_truck_ids.sort_unstable();
+    tow_truck_ids.dedup();
+    refresh_etas(order_service, tow_truck_ids);
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct StaleTowTruckQuery {
+    #[validate(range(min = 1))]
+    area: Option<i32>,
+}
+
+pub async fn get_stale_tow_trucks_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    query: ValidatedQuery<StaleTowTruckQuery>,
+) -> Result<HttpResponse, AppError> {
+    let tow_trucks = service.get_stale_tow_trucks(query.area).await?;
+
+    Ok(HttpResponse::Ok().json(tow_trucks))
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct TowTruckQuery {
+    #[validate(range(min = 1))]
     order_id: i32,
 }
 
@@ -70,7 +166,7 @@ pub async fn get_nearest_available_tow_trucks_handler(
     service: web::Data<
         TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
     >,
-    query: web::Query<TowTruckQuery>,
+    query: ValidatedQuery<TowTruckQuery>,
 ) -> Result<HttpResponse, AppError> {
     match service
         .get_nearest_available_tow_trucks(query.order_id)
@@ -81,3 +177,41 @@ pub async fn get_nearest_available_tow_trucks_handler(
         Err(err) => Err(err),
     }
 }
+
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_history_range"))]
+pub struct LocationHistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    expand: Option<bool>,
+}
+
+fn validate_history_range(query: &LocationHistoryQuery) -> Result<(), ValidationError> {
+    match (query.from, query.to) {
+        (Some(from), Some(to)) if from > to => {
+            let mut err = ValidationError::new("range");
+            err.message = Some("from は to 以前の日時を指定してください".into());
+            Err(err)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub async fn get_location_history_handler(
+    service: web::Data<
+        TowTruckService<TowTruckRepositoryImpl, OrderRepositoryImpl, MapRepositoryImpl>,
+    >,
+    path: web::Path<i32>,
+    query: ValidatedQuery<LocationHistoryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let history = service
+        .get_location_history(
+            path.into_inner(),
+            query.from,
+            query.to,
+            query.expand.unwrap_or(false),
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(history))
+}
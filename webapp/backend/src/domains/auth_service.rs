@@ -2,21 +2,12 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use actix_web::web::Bytes;
-use log::error;
+use tracing::{error, instrument};
 
-use std::fs::File;
-use std::io::Read;
-use std::time::Instant;
-
-use crate::errors::AppError;
+use crate::errors::{AppError, ErrorCode, FieldError};
 use crate::models::user::{Dispatcher, Session, User};
 use crate::utils::{generate_session_token, hash_password, verify_password};
 
-extern crate image;
-
-use image::{GenericImageView, ImageBuffer, DynamicImage, ImageOutputFormat, Rgb, RgbImage};
-use std::io::Cursor;
-
 use super::dto::auth::LoginResponseDto;
 
 pub trait AuthRepository {
@@ -36,6 +27,11 @@ pub trait AuthRepository {
     ) -> Result<Option<String>, AppError>;
     async fn create_session(&self, user_id: i32, session_token: &str) -> Result<(), AppError>;
     async fn delete_session(&self, session_token: &str) -> Result<(), AppError>;
+    // 有効なセッションのユーザーをセッションと一緒に1回のクエリで引く
+    async fn find_user_by_valid_session_token(
+        &self,
+        session_token: &str,
+    ) -> Result<Option<User>, AppError>;
     async fn find_session_by_session_token(&self, session_token: &str)
         -> Result<Session, AppError>;
 }
@@ -50,6 +46,7 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         AuthService { repository }
     }
 
+    #[instrument(skip(self, password))]
     pub async fn register_user(
         &self,
         username: &str,
@@ -58,14 +55,17 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         area: Option<i32>,
     ) -> Result<LoginResponseDto, AppError> {
         if role == "dispatcher" && area.is_none() {
-            return Err(AppError::BadRequest);
+            return Err(AppError::Validation(vec![FieldError::new(
+                "area_id",
+                "dispatcher には area_id が必要です",
+            )]));
         }
 
         if (self.repository.find_user_by_username(username).await?).is_some() {
             return Err(AppError::Conflict);
         }
 
-        let hashed_password = hash_password(password).unwrap();
+        let hashed_password = hash_password(password)?;
 
         self.repository
             .create_user(username, &hashed_password, role)
@@ -80,14 +80,13 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
                     .await?;
                 match user.role.as_str() {
                     "dispatcher" => {
-                        self.repository
-                            .create_dispatcher(user.id, area.unwrap())
-                            .await?;
+                        let area_id = area.ok_or(AppError::InternalServerError)?;
+                        self.repository.create_dispatcher(user.id, area_id).await?;
                         let dispatcher = self
                             .repository
                             .find_dispatcher_by_user_id(user.id)
                             .await?
-                            .unwrap();
+                            .ok_or(AppError::Coded(ErrorCode::DispatcherNotFound))?;
                         Ok(LoginResponseDto {
                             user_id: user.id,
                             username: user.username,
@@ -111,22 +110,18 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         }
     }
 
+    #[instrument(skip(self, password))]
     pub async fn login_user(
         &self,
         username: &str,
         password: &str,
     ) -> Result<LoginResponseDto, AppError> {
-        let login_start = Instant::now();
         match self.repository.find_user_by_username(username).await? {
             Some(user) => {
-                let login_duration0 = login_start.elapsed();
-                //println!("login_user0 时间间隔: {:?}", login_duration0);
-                let is_password_valid = verify_password(&user.password, password).unwrap();
+                let is_password_valid = verify_password(&user.password, password)?;
                 if !is_password_valid {
                     return Err(AppError::Unauthorized);
                 }
-                let login_duration1 = login_start.elapsed();
-                //println!("login_user1 时间间隔: {:?}", login_duration1);
 
                 let session_token = generate_session_token();
                 self.repository
@@ -163,11 +158,13 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         }
     }
 
+    #[instrument(skip(self, session_token))]
     pub async fn logout_user(&self, session_token: &str) -> Result<(), AppError> {
         self.repository.delete_session(session_token).await?;
         Ok(())
     }
 
+    #[instrument(skip(self))]
     pub async fn get_resized_profile_image_byte(
         &self,
         user_id: i32,
@@ -175,9 +172,6 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
         height: i32,
     ) -> Result<Bytes, AppError> {
 
-        let resized_start = Instant::now();
-
-
         let profile_image_name = match self
             .repository
             .find_profile_image_name_by_user_id(user_id)
@@ -236,9 +230,6 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
                 AppError::InternalServerError
             })?;
 
-        let resized_duration1 = resized_start.elapsed();
-        println!("resized_duration1 时间间隔: {:?}", resized_duration1);
-
         match output.status.success() {
             true => Ok(Bytes::from(output.stdout)),
             false => {
@@ -325,6 +316,7 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
 
     }
 
+    #[instrument(skip(self, session_token))]
     pub async fn validate_session(&self, session_token: &str) -> Result<bool, AppError> {
         let session = self
             .repository
@@ -333,4 +325,20 @@ impl<T: AuthRepository + std::fmt::Debug> AuthService<T> {
 
         Ok(session.is_valid)
     }
+
+    // 有効なセッションに紐づくユーザーを返す。無効化済みのセッションは None
+    #[instrument(skip(self, session_token))]
+    pub async fn authenticate(&self, session_token: &str) -> Result<Option<User>, AppError> {
+        self.repository
+            .find_user_by_valid_session_token(session_token)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn find_dispatcher_by_user_id(
+        &self,
+        user_id: i32,
+    ) -> Result<Option<Dispatcher>, AppError> {
+        self.repository.find_dispatcher_by_user_id(user_id).await
+    }
 }
@@ -0,0 +1,299 @@
+use super::auth_service::AuthRepository;
+use super::dto::order::AutoDispatchStatusDto;
+use super::map_service::MapRepository;
+use super::order_service::{OrderRepository, OrderService};
+use super::tow_truck_service::{TowTruckRepository, TowTruckService};
+use crate::errors::{AppError, ErrorCode};
+use crate::infrastructure::db::env_or;
+use crate::infrastructure::metrics::metrics;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// 自動配車の対象エリアと、配車担当者による停止・保留の状態
+#[derive(Debug)]
+pub struct AutoDispatchControl {
+    // 有効な間はすべてのエリアで自動配車を止める。環境変数でだけ切り替える
+    kill_switch: AtomicBool,
+    // 配車担当者が緊急停止したエリア
+    killed_areas: Mutex<HashSet<i32>>,
+    areas: Mutex<HashSet<i32>>,
+    // 配車担当者が手動で扱うため、自動配車しない依頼と、そのエリア
+    held_orders: Mutex<HashMap<i32, i32>>,
+    interval: Duration,
+    // 受け付けてからこの秒数が経つまでは、配車担当者が手動で割り当てる猶予として待つ
+    grace_secs: i64,
+}
+
+impl AutoDispatchControl {
+    // AUTO_DISPATCH_AREAS はカンマ区切りのエリア ID
+    pub fn from_env() -> Self {
+        let areas = env_or("AUTO_DISPATCH_AREAS", String::new())
+            .split(',')
+            .filter_map(|area_id| area_id.trim().parse::<i32>().ok())
+            .collect();
+
+        AutoDispatchControl {
+            kill_switch: AtomicBool::new(env_or("AUTO_DISPATCH_KILL_SWITCH", false)),
+            killed_areas: Mutex::new(HashSet::new()),
+            areas: Mutex::new(areas),
+            held_orders: Mutex::new(HashMap::new()),
+            interval: Duration::from_secs(env_or("AUTO_DISPATCH_INTERVAL_SECS", 5).max(1)),
+            grace_secs: env_or("AUTO_DISPATCH_GRACE_SECS", 15),
+        }
+    }
+
+    fn is_killed_globally(&self) -> bool {
+        self.kill_switch.load(Ordering::Relaxed)
+    }
+
+    pub fn is_killed(&self, area_id: i32) -> bool {
+        self.is_killed_globally() || self.killed_areas.lock().unwrap().contains(&area_id)
+    }
+
+    pub fn set_kill_switch(&self, area_id: i32, engaged: bool) {
+        let mut killed_areas = self.killed_areas.lock().unwrap();
+        if engaged {
+            killed_areas.insert(area_id);
+        } else {
+            killed_areas.remove(&area_id);
+        }
+    }
+
+    pub fn is_enabled(&self, area_id: i32) -> bool {
+        self.areas.lock().unwrap().contains(&area_id)
+    }
+
+    pub fn set_enabled(&self, area_id: i32, enabled: bool) {
+        let mut areas = self.areas.lock().unwrap();
+        if enabled {
+            areas.insert(area_id);
+        } else {
+            areas.remove(&area_id);
+        }
+    }
+
+    pub fn is_held(&self, order_id: i32) -> bool {
+        self.held_orders.lock().unwrap().contains_key(&order_id)
+    }
+
+    pub fn set_held(&self, order_id: i32, area_id: i32, held: bool) {
+        let mut held_orders = self.held_orders.lock().unwrap();
+        if held {
+            held_orders.insert(order_id, area_id);
+        } else {
+            held_orders.remove(&order_id);
+        }
+    }
+
+    // 配車待ちでなくなった依頼の保留を外す
+    fn prune_held(&self, area_id: i32, pending_order_ids: &[i32]) {
+        self.held_orders
+            .lock()
+            .unwrap()
+            .retain(|order_id, held_area_id| {
+                *held_area_id != area_id || pending_order_ids.contains(order_id)
+            });
+    }
+
+    fn held_areas(&self) -> Vec<i32> {
+        let mut areas: Vec<i32> = self.held_orders.lock().unwrap().values().copied().collect();
+        areas.sort_unstable();
+        areas.dedup();
+        areas
+    }
+
+    pub fn status(&self, area_id: i32, pending_order_ids: &[i32]) -> AutoDispatchStatusDto {
+        let held_orders = self.held_orders.lock().unwrap();
+        AutoDispatchStatusDto {
+            area_id,
+            enabled: self.is_enabled(area_id),
+            kill_switch_engaged: self.is_killed(area_id),
+            held_order_ids: pending_order_ids
+                .iter()
+                .copied()
+                .filter(|order_id| held_orders.contains_key(order_id))
+                .collect(),
+        }
+    }
+
+    fn enabled_areas(&self) -> Vec<i32> {
+        let mut areas: Vec<i32> = self.areas.lock().unwrap().iter().copied().collect();
+        areas.sort_unstable();
+        areas
+    }
+
+    // 停止中や対象外になった場合は、処理中の周回でも残りの依頼を割り当てない
+    fn should_dispatch(&self, area_id: i32) -> bool {
+        !self.is_killed(area_id) && self.is_enabled(area_id)
+    }
+}
+
+// 一定間隔で対象エリアの配車待ちの依頼を古い順に、最寄りの空きレッカー車へ割り当てる
+pub async fn run_auto_dispatch<T, U, V, W>(
+    control: Arc<AutoDispatchControl>,
+    tow_truck_service: Arc<TowTruckService<T, U, W>>,
+    order_service: Arc<OrderService<U, T, V, W>>,
+) where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    let mut interval = tokio::time::interval(control.interval);
+    loop {
+        interval.tick().await;
+        for area_id in control.held_areas() {
+            if let Err(err) = prune_held_orders(&control, &order_service, area_id).await {
+                warn!(area_id, error = ?err, "保留中の依頼の整理に失敗しました");
+            }
+        }
+        if control.is_killed_globally() {
+            continue;
+        }
+
+        for area_id in control.enabled_areas() {
+            if let Err(err) =
+                dispatch_area(&control, &tow_truck_service, &order_service, area_id).await
+            {
+                warn!(area_id, error = ?err, "自動配車に失敗しました");
+            }
+        }
+    }
+}
+
+async fn prune_held_orders<T, U, V, W>(
+    control: &AutoDispatchControl,
+    order_service: &OrderService<U, T, V, W>,
+    area_id: i32,
+) -> Result<(), AppError>
+where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    let pending_order_ids: Vec<i32> = order_service
+        .get_pending_orders(area_id)
+        .await?
+        .iter()
+        .map(|order| order.id)
+        .collect();
+    control.prune_held(area_id, &pending_order_ids);
+
+    Ok(())
+}
+
+async fn dispatch_area<T, U, V, W>(
+    control: &AutoDispatchControl,
+    tow_truck_service: &TowTruckService<T, U, W>,
+    order_service: &OrderService<U, T, V, W>,
+    area_id: i32,
+) -> Result<(), AppError>
+where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    let mut orders = order_service.get_pending_orders(area_id).await?;
+    orders.sort_by(|a, b| a.order_time.cmp(&b.order_time).then(a.id.cmp(&b.id)));
+
+    let received_before = Utc::now() - chrono::Duration::seconds(control.grace_secs);
+    for order in orders {
+        if !control.should_dispatch(area_id) {
+            return Ok(());
+        }
+        if control.is_held(order.id) || order.order_time > received_before {
+            continue;
+        }
+
+        let tow_truck = match tow_truck_service
+            .get_nearest_available_tow_trucks(order.id)
+            .await?
+        {
+            Some(tow_truck) => tow_truck,
+            None => {
+                metrics().inc_auto_dispatch(area_id, "no_tow_truck");
+                continue;
+            }
+        };
+
+        // 手動の配車と競合した場合は、その依頼を飛ばして次へ進む
+        match order_service
+            .auto_dispatch_order(area_id, order.id, tow_truck.id)
+            .await
+        {
+            Ok(()) => {
+                metrics().inc_auto_dispatch(area_id, "dispatched");
+                info!(
+                    area_id,
+                    order_id = order.id,
+                    tow_truck_id = tow_truck.id,
+                    "自動配車しました"
+                );
+            }
+            Err(err)
+                if matches!(
+                    err.code(),
+                    ErrorCode::PlanOutdated | ErrorCode::TowTruckUnavailable
+                ) =>
+            {
+                metrics().inc_auto_dispatch(area_id, "conflict");
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control() -> AutoDispatchControl {
+        AutoDispatchControl {
+            kill_switch: AtomicBool::new(false),
+            killed_areas: Mutex::new(HashSet::new()),
+            areas: Mutex::new(HashSet::from([1, 2])),
+            held_orders: Mutex::new(HashMap::new()),
+            interval: Duration::from_secs(5),
+            grace_secs: 0,
+        }
+    }
+
+    #[test]
+    fn kill_switch_stops_only_the_dispatchers_area() {
+        let control = control();
+        control.set_kill_switch(1, true);
+        assert!(!control.should_dispatch(1));
+        assert!(control.should_dispatch(2));
+
+        control.set_kill_switch(1, false);
+        assert!(control.should_dispatch(1));
+
+        // 環境変数の緊急停止はすべてのエリアに効く
+        control.kill_switch.store(true, Ordering::Relaxed);
+        assert!(!control.should_dispatch(1));
+        assert!(!control.should_dispatch(2));
+    }
+
+    #[test]
+    fn orders_that_left_pending_are_no_longer_held() {
+        let control = control();
+        control.set_held(10, 1, true);
+        control.set_held(11, 1, true);
+        control.set_held(20, 2, true);
+        assert_eq!(control.held_areas(), vec![1, 2]);
+
+        control.prune_held(1, &[11]);
+        assert!(!control.is_held(10));
+        assert!(control.is_held(11));
+        // 他のエリアの保留はそのまま
+        assert!(control.is_held(20));
+    }
+}
@@ -0,0 +1,129 @@
+use super::auth_service::AuthRepository;
+use super::map_service::MapRepository;
+use super::order_service::{OrderRepository, OrderService};
+use super::tow_truck_service::{TowTruckRepository, TowTruckService};
+use crate::errors::{AppError, ErrorCode};
+use crate::infrastructure::db::env_or;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// ドライバーが割り当ての申し出に応答するまでの猶予
+#[derive(Debug, Clone)]
+pub struct OfferConfig {
+    // 0 の場合は期限切れにしない
+    timeout_secs: i64,
+    interval: Duration,
+}
+
+impl OfferConfig {
+    pub fn from_env() -> Self {
+        OfferConfig {
+            // 応答しないドライバーがいると依頼が付け替わってしまうので、既定では無効にしておく
+            timeout_secs: env_or("OFFER_TIMEOUT_SECS", 0),
+            interval: Duration::from_secs(env_or("OFFER_CHECK_INTERVAL_SECS", 5).max(1)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeout().is_some()
+    }
+
+    pub fn timeout(&self) -> Option<chrono::Duration> {
+        (self.timeout_secs > 0).then(|| chrono::Duration::seconds(self.timeout_secs))
+    }
+
+    // offered_at に申し出た場合の期限。無効の場合は None
+    pub fn expires_at(&self, offered_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.timeout().map(|timeout| offered_at + timeout)
+    }
+}
+
+// 期限を過ぎた申し出を期限切れにし、辞退・期限切れの依頼を次のレッカー車に付け替える
+pub async fn run_offer_expiry<T, U, V, W>(
+    config: OfferConfig,
+    tow_truck_service: Arc<TowTruckService<T, U, W>>,
+    order_service: Arc<OrderService<U, T, V, W>>,
+) where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        interval.tick().await;
+        let offers = match order_service.expire_offers().await {
+            Ok(offers) => offers,
+            Err(err) => {
+                warn!(error = ?err, "申し出の期限の確認に失敗しました");
+                continue;
+            }
+        };
+
+        for offer in offers {
+            if let Err(err) = fall_back(
+                &tow_truck_service,
+                &order_service,
+                offer.order_id,
+                offer.tow_truck_id,
+            )
+            .await
+            {
+                warn!(order_id = offer.order_id, error = ?err, "申し出の付け替えに失敗しました");
+            }
+        }
+    }
+}
+
+// 辞退・期限切れになった tow_truck_id の代わりに、これまで応じなかったレッカー車を除いた次の候補へ申し出る
+// 候補がなければ配車待ちに戻す
+pub async fn fall_back<T, U, V, W>(
+    tow_truck_service: &TowTruckService<T, U, W>,
+    order_service: &OrderService<U, T, V, W>,
+    order_id: i32,
+    tow_truck_id: i32,
+) -> Result<(), AppError>
+where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    // 配車済みでなくなった依頼のために候補を探さない
+    if !order_service.is_order_dispatched(order_id).await? {
+        return Ok(());
+    }
+
+    let excluded = order_service.get_rejected_tow_truck_ids(order_id).await?;
+    let next = tow_truck_service
+        .get_next_available_tow_truck(order_id, &excluded)
+        .await?
+        .map(|tow_truck| tow_truck.id);
+
+    match order_service
+        .fall_back_offer(order_id, tow_truck_id, next)
+        .await
+    {
+        Ok(()) => {
+            info!(
+                order_id,
+                from_tow_truck_id = tow_truck_id,
+                to_tow_truck_id = ?next,
+                "申し出を次のレッカー車に付け替えました"
+            );
+            Ok(())
+        }
+        // 配車担当者が先に付け替えた、または依頼が取り消された
+        Err(err)
+            if matches!(
+                err.code(),
+                ErrorCode::OfferNotPending | ErrorCode::OrderNotDispatched
+            ) =>
+        {
+            Ok(())
+        }
+        Err(err) => Err(err),
+    }
+}
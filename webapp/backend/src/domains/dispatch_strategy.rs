@@ -0,0 +1,541 @@
+use crate::infrastructure::db::env_or;
+use crate::models::graph::Graph;
+use crate::models::order::Order;
+use crate::models::tow_truck::TowTruck;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+// これより遠いレッカー車は配車候補にしない
+pub const MAX_DISPATCH_DISTANCE: i32 = 10_000_000;
+
+// 配車候補のレッカー車と、戦略が参照する稼働状況
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub tow_truck: TowTruck,
+    // 最後に依頼を担当した時刻。担当したことがなければ None
+    pub last_active_time: Option<DateTime<Utc>>,
+    // 集計期間内に割り当てられた依頼の数
+    pub recent_orders: i64,
+}
+
+pub trait DistanceOracle {
+    // 道路は双方向なので、始点にはレッカー車のノードを渡して依頼をまたいで探索を使い回す
+    // 道路上の距離。到達できない、または MAX_DISPATCH_DISTANCE を超える場合は None
+    fn distance(&self, from_node_id: i32, to_node_id: i32) -> Option<i32>;
+}
+
+// エリアのグラフから距離を求める。始点ごとの最短距離は一度だけ計算する
+pub struct GraphDistanceOracle<'a> {
+    graph: &'a Graph,
+    distances: Mutex<HashMap<i32, Arc<HashMap<i32, i32>>>>,
+}
+
+impl<'a> GraphDistanceOracle<'a> {
+    pub fn new(graph: &'a Graph) -> Self {
+        GraphDistanceOracle {
+            graph,
+            distances: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl DistanceOracle for GraphDistanceOracle<'_> {
+    fn distance(&self, from_node_id: i32, to_node_id: i32) -> Option<i32> {
+        let distances = self
+            .distances
+            .lock()
+            .unwrap()
+            .entry(from_node_id)
+            .or_insert_with(|| Arc::new(self.graph.distances_from(from_node_id)))
+            .clone();
+
+        distances
+            .get(&to_node_id)
+            .copied()
+            .filter(|&distance| distance <= MAX_DISPATCH_DISTANCE)
+    }
+}
+
+pub trait DispatchStrategy: std::fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Candidate の稼働状況を参照する場合は true。false の場合は集計を省く
+    fn uses_workload(&self) -> bool {
+        false
+    }
+
+    // 同じエリアの他の配車待ちの依頼を参照する場合は true
+    fn uses_pending_orders(&self) -> bool {
+        false
+    }
+
+    // order に割り当てる候補の添字を返す。割り当てられる候補がなければ None
+    fn select(
+        &self,
+        order: &Order,
+        pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<usize>;
+}
+
+// 到達できる候補のうち score が最小のもの。同点は ID の小さい方
+fn select_min_by_score(
+    order: &Order,
+    candidates: &[Candidate],
+    oracle: &dyn DistanceOracle,
+    score: impl Fn(&Candidate, i32) -> f64,
+) -> Option<usize> {
+    candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, candidate)| {
+            let distance = oracle.distance(candidate.tow_truck.node_id, order.node_id)?;
+            Some((score(candidate, distance), candidate.tow_truck.id, index))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+        .map(|(_, _, index)| index)
+}
+
+// 道路上の距離が最も短いレッカー車
+#[derive(Debug)]
+pub struct Nearest;
+
+impl DispatchStrategy for Nearest {
+    fn name(&self) -> &'static str {
+        "nearest"
+    }
+
+    fn select(
+        &self,
+        order: &Order,
+        _pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<usize> {
+        select_min_by_score(order, candidates, oracle, |_, distance| distance as f64)
+    }
+}
+
+// 距離から待機時間に応じた値を差し引き、長く待っているレッカー車を優先する
+#[derive(Debug)]
+pub struct IdleFairness {
+    // 待機1分あたりに差し引く距離
+    pub idle_weight: f64,
+    // 待機時間の上限 (秒)。一度も担当していないレッカー車もこの値として扱う
+    pub max_idle_secs: i64,
+}
+
+impl DispatchStrategy for IdleFairness {
+    fn name(&self) -> &'static str {
+        "idle_fairness"
+    }
+
+    fn uses_workload(&self) -> bool {
+        true
+    }
+
+    fn select(
+        &self,
+        order: &Order,
+        _pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<usize> {
+        let now = Utc::now();
+        select_min_by_score(order, candidates, oracle, |candidate, distance| {
+            let idle_secs = candidate
+                .last_active_time
+                .map(|time| (now - time).num_seconds().clamp(0, self.max_idle_secs))
+                .unwrap_or(self.max_idle_secs);
+            distance as f64 - self.idle_weight * idle_secs as f64 / 60.0
+        })
+    }
+}
+
+// 車両価格の高い依頼から順に最寄りのレッカー車を割り当てたときに、order に回ってくるもの
+#[derive(Debug)]
+pub struct HighestCarValue;
+
+impl DispatchStrategy for HighestCarValue {
+    fn name(&self) -> &'static str {
+        "highest_car_value"
+    }
+
+    fn uses_pending_orders(&self) -> bool {
+        true
+    }
+
+    fn select(
+        &self,
+        order: &Order,
+        pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<usize> {
+        let mut orders: Vec<&Order> = pending_orders
+            .iter()
+            .filter(|pending| pending.id != order.id)
+            .chain(std::iter::once(order))
+            .collect();
+        // 同じ価格なら先に受け付けた依頼を優先する
+        orders.sort_by(|a, b| {
+            b.car_value
+                .total_cmp(&a.car_value)
+                .then(a.order_time.cmp(&b.order_time))
+                .then(a.id.cmp(&b.id))
+        });
+
+        // 先の依頼に回ったレッカー車は印を付けて候補から外す
+        let mut taken = vec![false; candidates.len()];
+        let mut remaining = candidates.len();
+        for current in orders {
+            if remaining == 0 {
+                return None;
+            }
+            let selected = candidates
+                .iter()
+                .enumerate()
+                .filter(|&(index, _)| !taken[index])
+                .filter_map(|(index, candidate)| {
+                    let distance = oracle.distance(candidate.tow_truck.node_id, current.node_id)?;
+                    Some((distance, candidate.tow_truck.id, index))
+                })
+                .min()
+                .map(|(_, _, index)| index);
+
+            if current.id == order.id {
+                return selected;
+            }
+            if let Some(index) = selected {
+                taken[index] = true;
+                remaining -= 1;
+            }
+        }
+
+        None
+    }
+}
+
+// 距離に直近の担当件数に応じた値を加え、担当の少ないレッカー車を優先する
+#[derive(Debug)]
+pub struct BalancedWorkload {
+    // 担当1件あたりに加える距離
+    pub workload_weight: f64,
+}
+
+impl DispatchStrategy for BalancedWorkload {
+    fn name(&self) -> &'static str {
+        "balanced_workload"
+    }
+
+    fn uses_workload(&self) -> bool {
+        true
+    }
+
+    fn select(
+        &self,
+        order: &Order,
+        _pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<usize> {
+        select_min_by_score(order, candidates, oracle, |candidate, distance| {
+            distance as f64 + self.workload_weight * candidate.recent_orders as f64
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DispatchConfig {
+    default: Arc<dyn DispatchStrategy>,
+    areas: HashMap<i32, Arc<dyn DispatchStrategy>>,
+    // 稼働状況を集計する期間 (秒)
+    pub workload_window_secs: i64,
+}
+
+impl DispatchConfig {
+    // DISPATCH_STRATEGY が全体の既定値、DISPATCH_STRATEGY_BY_AREA ("1=balanced_workload,3=nearest") がエリアごとの指定
+    pub fn from_env() -> Self {
+        let workload_window_secs = env_or("DISPATCH_WORKLOAD_WINDOW_SECS", 86400);
+        let idle_weight = env_or("DISPATCH_IDLE_WEIGHT", 10.0);
+        let workload_weight = env_or("DISPATCH_WORKLOAD_WEIGHT", 100.0);
+
+        let build = |name: &str| -> Arc<dyn DispatchStrategy> {
+            match name.trim() {
+                "nearest" => Arc::new(Nearest),
+                "idle_fairness" => Arc::new(IdleFairness {
+                    idle_weight,
+                    max_idle_secs: workload_window_secs,
+                }),
+                "highest_car_value" => Arc::new(HighestCarValue),
+                "balanced_workload" => Arc::new(BalancedWorkload { workload_weight }),
+                other => {
+                    warn!(strategy = other, "不明な配車戦略のため nearest を使います");
+                    Arc::new(Nearest)
+                }
+            }
+        };
+
+        let default = build(&env_or("DISPATCH_STRATEGY", "nearest".to_string()));
+        let mut areas = HashMap::new();
+        for entry in env_or("DISPATCH_STRATEGY_BY_AREA", String::new())
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            match entry
+                .split_once('=')
+                .and_then(|(area_id, name)| Some((area_id.trim().parse::<i32>().ok()?, name)))
+            {
+                Some((area_id, name)) => {
+                    areas.insert(area_id, build(name));
+                }
+                None => warn!(entry, "配車戦略のエリア指定を読み取れません"),
+            }
+        }
+
+        DispatchConfig {
+            default,
+            areas,
+            workload_window_secs,
+        }
+    }
+
+    pub fn strategy_for(&self, area_id: i32) -> &dyn DispatchStrategy {
+        self.areas.get(&area_id).unwrap_or(&self.default).as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::graph::{Edge, Node};
+    use std::collections::HashSet;
+
+    // 依頼のノードとレッカー車のノードの組ごとに決めた距離。登録のない組は到達できない
+    struct FixedDistances(HashMap<(i32, i32), i32>);
+
+    impl DistanceOracle for FixedDistances {
+        fn distance(&self, from_node_id: i32, to_node_id: i32) -> Option<i32> {
+            self.0.get(&(to_node_id, from_node_id)).copied()
+        }
+    }
+
+    fn order(id: i32, node_id: i32, car_value: f64) -> Order {
+        Order {
+            id,
+            client_id: 1,
+            dispatcher_id: None,
+            tow_truck_id: None,
+            status: "pending".to_string(),
+            node_id,
+            car_value,
+            order_time: Utc::now(),
+            completed_time: None,
+            escalated_at: None,
+        }
+    }
+
+    fn candidate(id: i32, node_id: i32) -> Candidate {
+        Candidate {
+            tow_truck: TowTruck {
+                id,
+                driver_id: id,
+                driver_username: None,
+                status: "available".to_string(),
+                area_id: 1,
+                node_id,
+                last_seen: Utc::now(),
+            },
+            last_active_time: None,
+            recent_orders: 0,
+        }
+    }
+
+    fn oracle(distances: &[((i32, i32), i32)]) -> FixedDistances {
+        FixedDistances(distances.iter().copied().collect())
+    }
+
+    fn selected_id(
+        strategy: &dyn DispatchStrategy,
+        order: &Order,
+        pending_orders: &[Order],
+        candidates: &[Candidate],
+        oracle: &dyn DistanceOracle,
+    ) -> Option<i32> {
+        strategy
+            .select(order, pending_orders, candidates, oracle)
+            .map(|index| candidates[index].tow_truck.id)
+    }
+
+    #[test]
+    fn nearest_prefers_shorter_distance_then_smaller_id() {
+        let order = order(1, 100, 1000.0);
+        let candidates = vec![candidate(3, 13), candidate(2, 12), candidate(1, 11)];
+        let oracle = oracle(&[((100, 11), 50), ((100, 12), 30), ((100, 13), 30)]);
+
+        assert_eq!(
+            selected_id(&Nearest, &order, &[], &candidates, &oracle),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn unreachable_candidates_are_skipped() {
+        let order = order(1, 100, 1000.0);
+        let candidates = vec![candidate(1, 11), candidate(2, 12)];
+
+        let reachable = oracle(&[((100, 12), 500)]);
+        assert_eq!(
+            selected_id(&Nearest, &order, &[], &candidates, &reachable),
+            Some(2)
+        );
+        let unreachable = oracle(&[]);
+        assert_eq!(
+            selected_id(&Nearest, &order, &[], &candidates, &unreachable),
+            None
+        );
+        assert_eq!(selected_id(&Nearest, &order, &[], &[], &reachable), None);
+    }
+
+    #[test]
+    fn idle_fairness_prefers_longer_idle_trucks() {
+        let strategy = IdleFairness {
+            idle_weight: 10.0,
+            max_idle_secs: 3600,
+        };
+        let order = order(1, 100, 1000.0);
+        let mut recent = candidate(1, 11);
+        recent.last_active_time = Some(Utc::now());
+        let mut idle = candidate(2, 12);
+        idle.last_active_time = Some(Utc::now() - chrono::Duration::minutes(30));
+        let oracle = oracle(&[((100, 11), 100), ((100, 12), 300), ((100, 13), 550)]);
+
+        // 30分の待機で 300 差し引かれる
+        let candidates = vec![recent.clone(), idle.clone()];
+        assert_eq!(
+            selected_id(&strategy, &order, &[], &candidates, &oracle),
+            Some(2)
+        );
+
+        // 担当したことのないレッカー車は上限の60分待機として扱う
+        let never = candidate(3, 13);
+        let candidates = vec![recent, idle, never];
+        assert_eq!(
+            selected_id(&strategy, &order, &[], &candidates, &oracle),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn balanced_workload_prefers_fewer_recent_orders() {
+        let strategy = BalancedWorkload {
+            workload_weight: 100.0,
+        };
+        let order = order(1, 100, 1000.0);
+        let mut busy = candidate(1, 11);
+        busy.recent_orders = 3;
+        let mut light = candidate(2, 12);
+        light.recent_orders = 1;
+        let oracle = oracle(&[((100, 11), 100), ((100, 12), 250)]);
+
+        assert_eq!(
+            selected_id(&strategy, &order, &[], &[busy, light], &oracle),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn highest_car_value_lets_expensive_orders_choose_first() {
+        let cheap = order(1, 100, 1000.0);
+        let expensive = order(2, 200, 5000.0);
+        let candidates = vec![candidate(1, 11), candidate(2, 12)];
+        // どちらの依頼にとってもレッカー車1が近い
+        let oracle = oracle(&[
+            ((100, 11), 10),
+            ((100, 12), 20),
+            ((200, 11), 10),
+            ((200, 12), 90),
+        ]);
+        let pending = vec![cheap.clone(), expensive.clone()];
+
+        assert_eq!(
+            selected_id(&HighestCarValue, &cheap, &pending, &candidates, &oracle),
+            Some(2)
+        );
+        assert_eq!(
+            selected_id(&HighestCarValue, &expensive, &pending, &candidates, &oracle),
+            Some(1)
+        );
+        // 候補が足りなければ安い依頼には回ってこない
+        assert_eq!(
+            selected_id(
+                &HighestCarValue,
+                &cheap,
+                &pending,
+                &candidates[..1],
+                &oracle
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn highest_car_value_searches_from_tow_trucks_only() {
+        // 距離を求めた始点を記録する
+        struct RecordingOracle(FixedDistances, Mutex<HashSet<i32>>);
+
+        impl DistanceOracle for RecordingOracle {
+            fn distance(&self, from_node_id: i32, to_node_id: i32) -> Option<i32> {
+                self.1.lock().unwrap().insert(from_node_id);
+                self.0.distance(from_node_id, to_node_id)
+            }
+        }
+
+        let target = order(1, 100, 1000.0);
+        let pending: Vec<Order> = (2..=20)
+            .map(|id| order(id, 100 + id, 5000.0))
+            .chain(std::iter::once(target.clone()))
+            .collect();
+        let candidates = vec![candidate(1, 11), candidate(2, 12), candidate(3, 13)];
+        let oracle = RecordingOracle(
+            oracle(&[((100, 11), 10), ((102, 12), 10), ((103, 13), 10)]),
+            Mutex::new(HashSet::new()),
+        );
+
+        // 到達できない高額の依頼はレッカー車を使わない
+        assert_eq!(
+            selected_id(&HighestCarValue, &target, &pending, &candidates, &oracle),
+            Some(1)
+        );
+        assert_eq!(*oracle.1.lock().unwrap(), HashSet::from([11, 12, 13]));
+    }
+
+    #[test]
+    fn graph_oracle_uses_road_distance() {
+        let mut graph = Graph::new();
+        for id in 1..=4 {
+            graph.add_node(Node {
+                id,
+                area_id: 1,
+                x: id,
+                y: 0,
+            });
+        }
+        for (node_a_id, node_b_id, weight) in [(1, 2, 5), (2, 3, 5), (1, 3, 20)] {
+            graph.add_edge(Edge {
+                node_a_id,
+                node_b_id,
+                weight,
+            });
+        }
+        let oracle = GraphDistanceOracle::new(&graph);
+
+        assert_eq!(oracle.distance(1, 3), Some(10));
+        assert_eq!(oracle.distance(3, 1), Some(10));
+        assert_eq!(oracle.distance(1, 1), Some(0));
+        assert_eq!(oracle.distance(1, 4), None);
+    }
+}
@@ -1,23 +1,33 @@
 use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::validators::validate_user_role;
 
 // Input Data Structure
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct RegisterRequestDto {
+    #[validate(length(min = 1, max = 255))]
     pub username: String,
+    #[validate(length(min = 1, max = 255))]
     pub password: String,
+    #[validate(custom(function = "validate_user_role"))]
     pub role: String,
+    #[validate(range(min = 1))]
     pub area_id: Option<i32>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct LoginRequestDto {
+    #[validate(length(min = 1, max = 255))]
     pub username: String,
+    #[validate(length(min = 1, max = 255))]
     pub password: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct LogoutRequestDto {
+    #[validate(length(min = 1, max = 255))]
     pub session_token: String,
 }
 
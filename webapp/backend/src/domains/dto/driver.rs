@@ -0,0 +1,33 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::tow_truck::RouteSegmentDto;
+use super::validators::validate_job_status;
+
+// Input Data Structure
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UpdateJobStatusRequestDto {
+    #[validate(custom(function = "validate_job_status"))]
+    pub status: String,
+}
+
+// Output Data Structure
+
+// ドライバーのレッカー車に割り当てられた依頼
+#[derive(Serialize, Clone)]
+pub struct DriverJobDto {
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    // offered / accepted / en_route / on_site
+    pub status: String,
+    pub node_id: i32,
+    pub car_value: f64,
+    pub order_time: DateTime<Utc>,
+    // 応答待ちの申し出の期限
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offer_expires_at: Option<DateTime<Utc>>,
+    // レッカー車の現在地から依頼地点までの経路
+    pub route: RouteSegmentDto,
+}
@@ -1,10 +1,38 @@
 // Input Data Structure
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-#[derive(Deserialize, Debug)]
+use super::validators::validate_finite;
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct UpdateEdgeRequestDto {
+    #[validate(range(min = 1))]
     pub node_a_id: i32,
+    #[validate(range(min = 1))]
     pub node_b_id: i32,
+    // 0 以下の重みは最短経路探索を壊すので受け付けない
+    #[validate(range(min = 1))]
     pub weight: i32,
 }
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct NearestNodeQuery {
+    #[validate(custom(function = "validate_finite"))]
+    pub x: f64,
+    #[validate(custom(function = "validate_finite"))]
+    pub y: f64,
+    #[validate(range(min = 1))]
+    pub area: Option<i32>,
+}
+
+// Output Data Structure
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NearestNodeDto {
+    pub node_id: i32,
+    pub area_id: i32,
+    pub x: i32,
+    pub y: i32,
+    pub distance: f64,
+}
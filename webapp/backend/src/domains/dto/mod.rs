@@ -1,4 +1,6 @@
 pub mod auth;
+pub mod driver;
 pub mod map;
 pub mod order;
 pub mod tow_truck;
+pub mod validators;
@@ -1,29 +1,128 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use validator::{Validate, ValidationError};
+
+use super::validators::{
+    validate_cancel_reason, validate_finite, validate_node_or_coordinates,
+    validate_reassign_reason, validate_updatable_order_status,
+};
 
 // Input Data Structure
 
-#[derive(Deserialize, Debug)]
+// 地図上の座標で指定した場合は最も近いノードに寄せる。area_id を省略すると全エリアから探す
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_client_order_location"))]
 pub struct ClientOrderRequestDto {
+    #[validate(range(min = 1))]
     pub client_id: i32,
-    pub node_id: i32,
+    #[validate(range(min = 1))]
+    pub node_id: Option<i32>,
+    #[validate(custom(function = "validate_finite"))]
+    pub x: Option<f64>,
+    #[validate(custom(function = "validate_finite"))]
+    pub y: Option<f64>,
+    #[validate(range(min = 1))]
+    pub area_id: Option<i32>,
+    #[validate(range(min = 0.0), custom(function = "validate_finite"))]
     pub car_value: f64,
 }
 
-#[derive(Deserialize, Debug)]
+fn validate_client_order_location(req: &ClientOrderRequestDto) -> Result<(), ValidationError> {
+    validate_node_or_coordinates(req.node_id, req.x, req.y)
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct DispatcherOrderRequestDto {
+    #[validate(range(min = 1))]
     pub order_id: i32,
+    #[validate(range(min = 1))]
     pub dispatcher_id: i32,
+    #[validate(range(min = 1))]
     pub tow_truck_id: i32,
     pub order_time: DateTime<Utc>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Validate)]
 pub struct UpdateOrderStatusRequestDto {
+    #[validate(range(min = 1))]
     pub order_id: i32,
+    #[validate(custom(function = "validate_updatable_order_status"))]
     pub status: String,
 }
 
+#[derive(Deserialize, Debug, Validate)]
+pub struct CancelOrderRequestDto {
+    #[validate(custom(function = "validate_cancel_reason"))]
+    pub reason: String,
+    #[validate(length(max = 500))]
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct ReassignOrderRequestDto {
+    #[validate(range(min = 1))]
+    pub tow_truck_id: i32,
+    #[validate(custom(function = "validate_reassign_reason"))]
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UnassignOrderRequestDto {
+    #[validate(custom(function = "validate_reassign_reason"))]
+    pub reason: String,
+}
+
+// 割り当て計画のうち受け入れる組み合わせ。計画の assignments をそのまま送ってよい
+#[derive(Deserialize, Serialize, Debug, Validate)]
+pub struct AssignmentRequestDto {
+    #[validate(range(min = 1))]
+    pub order_id: i32,
+    #[validate(range(min = 1))]
+    pub tow_truck_id: i32,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_unique_assignments"))]
+pub struct AcceptAssignmentPlanRequestDto {
+    #[validate(length(min = 1, max = 1000), nested)]
+    pub assignments: Vec<AssignmentRequestDto>,
+}
+
+// 同じ依頼やレッカー車を2回割り当てることはできない
+fn validate_unique_assignments(
+    req: &AcceptAssignmentPlanRequestDto,
+) -> Result<(), ValidationError> {
+    let mut order_ids = HashSet::new();
+    let mut tow_truck_ids = HashSet::new();
+    for assignment in &req.assignments {
+        if !order_ids.insert(assignment.order_id) || !tow_truck_ids.insert(assignment.tow_truck_id)
+        {
+            return Err(ValidationError::new("assignments")
+                .with_message("依頼とレッカー車はそれぞれ1回だけ指定してください".into()));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UpdateAutoDispatchRequestDto {
+    pub enabled: bool,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct UpdateKillSwitchRequestDto {
+    pub engaged: bool,
+}
+
+// 保留中の依頼は自動配車せず、配車担当者が手動で割り当てる
+#[derive(Deserialize, Debug, Validate)]
+pub struct HoldOrderRequestDto {
+    #[validate(range(min = 1))]
+    pub order_id: i32,
+    pub held: bool,
+}
+
 // Output Data Structure
 
 #[derive(Serialize, Debug)]
@@ -43,6 +142,114 @@ pub struct OrderDto {
     pub car_value: f64,
     pub order_time: DateTime<Utc>,
     pub completed_time: Option<DateTime<Utc>>,
+    // 車両価格・待ち時間・エスカレーションから求めた点数。一覧の既定の並び順に使う
+    pub priority: f64,
+    pub escalated_at: Option<DateTime<Utc>>,
+    // 配車済みの依頼を個別に取得した場合だけ設定する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<EtaDto>,
+}
+
+// 割り当てられたレッカー車の現在位置から依頼地点までの到着見込み
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct EtaDto {
+    pub distance: i32,
+    pub seconds: i64,
+    pub estimated_arrival: DateTime<Utc>,
+}
+
+// 依頼者向けに WebSocket で配信する依頼の変化
+#[derive(Serialize, Clone, Debug)]
+pub struct OrderEventDto {
+    pub order_id: i32,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tow_truck_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta: Option<EtaDto>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct OrderCancellationDto {
+    pub order_id: i32,
+    pub reason: String,
+    pub note: Option<String>,
+    pub cancelled_by: i32,
+    pub cancelled_by_role: String,
+    // 割り当て済みだったために空きに戻したレッカー車
+    pub released_tow_truck_id: Option<i32>,
+    pub cancelled_at: DateTime<Utc>,
+}
+
+// 依頼へのレッカー車の割り当て履歴の1件。unassigned_at が null なら現在の割り当て
+#[derive(Serialize, Debug)]
+pub struct OrderAssignmentDto {
+    pub id: i32,
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    // 自動配車の場合は null
+    pub assigned_by: Option<i32>,
+    pub assigned_at: DateTime<Utc>,
+    pub unassigned_at: Option<DateTime<Utc>>,
+    pub unassigned_by: Option<i32>,
+    pub unassign_reason: Option<String>,
+    // ドライバーへの申し出の状態。offered / accepted / declined / expired
+    pub offer_status: String,
+    pub responded_at: Option<DateTime<Utc>>,
+    pub en_route_at: Option<DateTime<Utc>>,
+    pub on_site_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+// ドライバー向けに WebSocket で配信する割り当ての申し出
+#[derive(Serialize, Clone, Debug)]
+pub struct OfferEventDto {
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    pub node_id: i32,
+    pub car_value: f64,
+    // これまでに承諾・辞退しなければ次のレッカー車に付け替える。期限がなければ省く
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    pub timestamp: DateTime<Utc>,
+}
+
+// 配車担当者向けに SSE で配信する、SLA を超えた依頼
+#[derive(Serialize, Clone, Debug)]
+pub struct OrderEscalationEventDto {
+    pub order_id: i32,
+    pub area_id: i32,
+    pub order_time: DateTime<Utc>,
+    pub sla_secs: i64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PlannedAssignmentDto {
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    pub distance: i32,
+}
+
+// エリアの配車待ちの依頼と空きレッカー車の、距離の合計が最小になる割り当て
+#[derive(Serialize, Debug)]
+pub struct AssignmentPlanDto {
+    pub area_id: i32,
+    pub assignments: Vec<PlannedAssignmentDto>,
+    pub total_distance: i64,
+    // レッカー車が足りない、または到達できるレッカー車がない依頼
+    pub unassigned_order_ids: Vec<i32>,
+    pub unassigned_tow_truck_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AutoDispatchStatusDto {
+    pub area_id: i32,
+    pub enabled: bool,
+    pub kill_switch_engaged: bool,
+    // 配車待ちのうち保留中の依頼
+    pub held_order_ids: Vec<i32>,
 }
 
 #[derive(Serialize, Debug)]
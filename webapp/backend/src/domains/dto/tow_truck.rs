@@ -1,15 +1,85 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use super::validators::{validate_finite, validate_node_or_coordinates};
 
 // Input Data Structure
 
-#[derive(Deserialize, Debug)]
+// 座標で指定した場合は、レッカー車のエリア内で最も近いノードに寄せる
+#[derive(Deserialize, Debug, Validate)]
+#[validate(schema(function = "validate_update_location_target"))]
 pub struct UpdateLocationRequestDto {
+    #[validate(range(min = 1))]
+    pub tow_truck_id: i32,
+    #[validate(range(min = 1))]
+    pub node_id: Option<i32>,
+    #[validate(custom(function = "validate_finite"))]
+    pub x: Option<f64>,
+    #[validate(custom(function = "validate_finite"))]
+    pub y: Option<f64>,
+}
+
+fn validate_update_location_target(req: &UpdateLocationRequestDto) -> Result<(), ValidationError> {
+    validate_node_or_coordinates(req.node_id, req.x, req.y)
+}
+
+#[derive(Serialize, Deserialize, Debug, Validate)]
+pub struct LocationRecordDto {
+    #[validate(range(min = 1))]
     pub tow_truck_id: i32,
+    #[validate(range(min = 1))]
     pub node_id: i32,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Debug, Validate)]
+pub struct BatchUpdateLocationRequestDto {
+    #[validate(length(min = 1, max = 5000), nested)]
+    pub locations: Vec<LocationRecordDto>,
 }
 
 // Output Data Structure
 
+// SSE で配信するレッカー車の変化。location イベントは node_id、status イベントは status を持つ
+#[derive(Serialize, Clone, Debug)]
+pub struct TowTruckEventDto {
+    pub tow_truck_id: i32,
+    pub area_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    UnknownTowTruck,
+    NodeOutsideArea,
+    Duplicate,
+    Stale,
+    // サーバー時刻より先の時刻
+    FutureTimestamp,
+    // 直前の位置から経過時間内に到達できない
+    Implausible,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SkippedLocationDto {
+    // リクエストの locations 内での位置
+    pub index: usize,
+    pub tow_truck_id: i32,
+    pub reason: SkipReason,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchUpdateLocationResponseDto {
+    pub accepted: usize,
+    pub skipped: Vec<SkippedLocationDto>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct TowTruckDto {
     pub id: i32,
@@ -18,10 +88,40 @@ pub struct TowTruckDto {
     pub status: String,
     pub node_id: i32,
     pub area_id: i32,
+    pub last_seen: DateTime<Utc>,
+    // 最新位置が古く、配車対象から外れている
+    pub stale: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LocationSampleDto {
+    pub id: i32,
+    pub node_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub flagged: bool,
+}
+
+// 連続する2つのサンプル間の道路上の経路
+#[derive(Serialize, Clone)]
+pub struct RouteSegmentDto {
+    pub from_node_id: i32,
+    pub to_node_id: i32,
+    pub distance: Option<i32>,
+    pub node_ids: Vec<i32>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct LocationHistoryDto {
+    pub tow_truck_id: i32,
+    pub samples: Vec<LocationSampleDto>,
+    // 上限件数で打ち切った場合は true。続きは期間を区切って取得する
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<Vec<RouteSegmentDto>>,
 }
 
 impl TowTruckDto {
-    pub fn from_entity(entity: crate::models::tow_truck::TowTruck) -> Self {
+    pub fn from_entity(entity: crate::models::tow_truck::TowTruck, stale: bool) -> Self {
         TowTruckDto {
             id: entity.id,
             driver_user_id: entity.driver_id,
@@ -29,6 +129,8 @@ impl TowTruckDto {
             status: entity.status,
             node_id: entity.node_id,
             area_id: entity.area_id,
+            last_seen: entity.last_seen,
+            stale,
         }
     }
 }
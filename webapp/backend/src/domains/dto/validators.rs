@@ -0,0 +1,94 @@
+use validator::ValidationError;
+
+pub const USER_ROLES: &[&str] = &["client", "dispatcher", "driver"];
+pub const ORDER_STATUSES: &[&str] = &["pending", "dispatched", "completed", "cancelled"];
+// cancelled は取り消し API でだけ設定できる
+pub const UPDATABLE_ORDER_STATUSES: &[&str] = &["pending", "dispatched", "completed"];
+pub const CANCEL_REASONS: &[&str] = &[
+    "client_request",
+    "duplicate",
+    "resolved_on_site",
+    "unreachable",
+    "other",
+];
+// 割り当てを変更する理由。order_cancelled は取り消し時に記録する
+pub const REASSIGN_REASONS: &[&str] = &["breakdown", "driver_unavailable", "closer_truck", "other"];
+// ドライバーが報告する作業の段階。この順にだけ進められる
+pub const JOB_STATUSES: &[&str] = &["en_route", "on_site", "completed"];
+pub const TOW_TRUCK_STATUSES: &[&str] = &["available", "busy"];
+pub const ORDER_SORT_KEYS: &[&str] = &["priority", "car_value", "status", "order_time"];
+pub const SORT_ORDERS: &[&str] = &["asc", "ASC", "desc", "DESC"];
+
+fn one_of(value: &str, allowed: &[&str], code: &'static str) -> Result<(), ValidationError> {
+    if allowed.contains(&value) {
+        return Ok(());
+    }
+    Err(ValidationError::new(code)
+        .with_message(format!("{} のいずれかを指定してください", allowed.join(", ")).into()))
+}
+
+pub fn validate_user_role(role: &str) -> Result<(), ValidationError> {
+    one_of(role, USER_ROLES, "role")
+}
+
+pub fn validate_order_status(status: &str) -> Result<(), ValidationError> {
+    one_of(status, ORDER_STATUSES, "order_status")
+}
+
+pub fn validate_updatable_order_status(status: &str) -> Result<(), ValidationError> {
+    one_of(status, UPDATABLE_ORDER_STATUSES, "order_status")
+}
+
+pub fn validate_cancel_reason(reason: &str) -> Result<(), ValidationError> {
+    one_of(reason, CANCEL_REASONS, "reason")
+}
+
+pub fn validate_reassign_reason(reason: &str) -> Result<(), ValidationError> {
+    one_of(reason, REASSIGN_REASONS, "reason")
+}
+
+pub fn validate_job_status(status: &str) -> Result<(), ValidationError> {
+    one_of(status, JOB_STATUSES, "job_status")
+}
+
+pub fn validate_tow_truck_status(status: &str) -> Result<(), ValidationError> {
+    one_of(status, TOW_TRUCK_STATUSES, "tow_truck_status")
+}
+
+pub fn validate_order_sort_key(sort_by: &str) -> Result<(), ValidationError> {
+    one_of(sort_by, ORDER_SORT_KEYS, "sort_by")
+}
+
+pub fn validate_sort_order(sort_order: &str) -> Result<(), ValidationError> {
+    one_of(sort_order, SORT_ORDERS, "sort_order")
+}
+
+// serde_json は NaN を受け付けないが、桁あふれした値は無限大として読み込まれる
+pub fn validate_finite(value: f64) -> Result<(), ValidationError> {
+    if value.is_finite() {
+        return Ok(());
+    }
+    Err(ValidationError::new("finite").with_message("有限の数値を指定してください".into()))
+}
+
+// -1 は全件取得を表す
+pub fn validate_page_size_or_all(page_size: i32) -> Result<(), ValidationError> {
+    if page_size == -1 || (1..=1000).contains(&page_size) {
+        return Ok(());
+    }
+    Err(ValidationError::new("page_size")
+        .with_message("1 から 1000 まで、または全件取得の場合は -1 を指定してください".into()))
+}
+
+// 位置は node_id か (x, y) のどちらか一方で指定する
+pub fn validate_node_or_coordinates(
+    node_id: Option<i32>,
+    x: Option<f64>,
+    y: Option<f64>,
+) -> Result<(), ValidationError> {
+    match (node_id, x, y) {
+        (Some(_), None, None) | (None, Some(_), Some(_)) => Ok(()),
+        _ => Err(ValidationError::new("location")
+            .with_message("node_id または x, y のどちらか一方を指定してください".into())),
+    }
+}
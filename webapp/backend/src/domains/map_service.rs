@@ -1,12 +1,23 @@
+use super::dto::map::NearestNodeDto;
 use crate::{
-    errors::AppError,
-    models::graph::{Edge, Node},
+    errors::{AppError, ErrorCode},
+    models::{
+        graph::{Edge, Graph, Node},
+        spatial_index::GridIndex,
+    },
 };
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+use tracing::{instrument, warn};
 
 pub trait MapRepository {
     async fn get_all_nodes(&self, area_id: Option<i32>) -> Result<Vec<Node>, sqlx::Error>;
     async fn get_all_edges(&self, area_id: Option<i32>) -> Result<Vec<Edge>, sqlx::Error>;
     async fn get_area_id_by_node_id(&self, node_id: i32) -> Result<i32, sqlx::Error>;
+    async fn get_area_ids_by_node_ids(
+        &self,
+        node_ids: &[i32],
+    ) -> Result<Vec<(i32, i32)>, sqlx::Error>;
     async fn update_edge(
         &self,
         node_a_id: i32,
@@ -15,16 +26,90 @@ pub trait MapRepository {
     ) -> Result<(), sqlx::Error>;
 }
 
+#[derive(Default)]
+struct GraphCache {
+    graphs: HashMap<i32, Arc<Graph>>,
+    // 辺の更新のたびに増やす。読み込み中に更新された場合、古いグラフをキャッシュしないため
+    generation: u64,
+}
+
+// エリアごとの経路探索用グラフ。各サービスが同じものを使い、辺の更新で捨てる
+fn graph_cache() -> &'static RwLock<GraphCache> {
+    static GRAPH_CACHE: OnceLock<RwLock<GraphCache>> = OnceLock::new();
+    GRAPH_CACHE.get_or_init(Default::default)
+}
+
+// エリア内のノードと辺から経路探索用のグラフを作る。辺が更新されるまで使い回す
+pub async fn load_area_graph<T: MapRepository>(
+    repository: &T,
+    area_id: i32,
+) -> Result<Arc<Graph>, AppError> {
+    let generation = {
+        let cache = graph_cache().read().unwrap();
+        if let Some(graph) = cache.graphs.get(&area_id) {
+            return Ok(graph.clone());
+        }
+        cache.generation
+    };
+
+    let nodes = repository.get_all_nodes(Some(area_id)).await?;
+    let edges = repository.get_all_edges(Some(area_id)).await?;
+
+    let mut graph = Graph::new();
+    for node in nodes {
+        graph.add_node(node);
+    }
+    for edge in edges {
+        graph.add_edge(edge);
+    }
+    let graph = Arc::new(graph);
+
+    // 存在しないエリアの空グラフはキャッシュしない
+    let mut cache = graph_cache().write().unwrap();
+    if cache.generation == generation && !graph.nodes.is_empty() {
+        cache.graphs.insert(area_id, graph.clone());
+    }
+
+    Ok(graph)
+}
+
+// None の場合はすべてのエリアのグラフを捨てる
+fn invalidate_area_graph(area_id: Option<i32>) {
+    let mut cache = graph_cache().write().unwrap();
+    cache.generation += 1;
+    match area_id {
+        Some(area_id) => {
+            cache.graphs.remove(&area_id);
+        }
+        None => cache.graphs.clear(),
+    }
+}
+
+// キャッシュ済みのグラフの数とノード数の合計
+pub fn area_graph_stats() -> (usize, usize) {
+    let cache = graph_cache().read().unwrap();
+    (
+        cache.graphs.len(),
+        cache.graphs.values().map(|graph| graph.nodes.len()).sum(),
+    )
+}
+
 #[derive(Debug)]
 pub struct MapService<T: MapRepository + std::fmt::Debug> {
     repository: T,
+    // ノードは実行中に増減しないので、エリアごと (None は全エリア) に一度だけ作る
+    node_indexes: RwLock<HashMap<Option<i32>, Arc<GridIndex>>>,
 }
 
 impl<T: MapRepository + std::fmt::Debug> MapService<T> {
     pub fn new(repository: T) -> Self {
-        MapService { repository }
+        MapService {
+            repository,
+            node_indexes: RwLock::new(HashMap::new()),
+        }
     }
 
+    #[instrument(skip(self))]
     pub async fn update_edge(
         &self,
         node_a_id: i32,
@@ -35,6 +120,63 @@ impl<T: MapRepository + std::fmt::Debug> MapService<T> {
             .update_edge(node_a_id, node_b_id, weight)
             .await?;
 
+        // 辺は同じエリアのノード同士を結ぶ。エリアを引けなければ全エリア分を捨てる
+        match self.repository.get_area_id_by_node_id(node_a_id).await {
+            Ok(area_id) => invalidate_area_graph(Some(area_id)),
+            Err(err) => {
+                warn!(error = ?err, node_a_id, "辺のエリアを特定できません");
+                invalidate_area_graph(None);
+            }
+        }
+
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    pub async fn snap_to_node(
+        &self,
+        area_id: Option<i32>,
+        x: f64,
+        y: f64,
+    ) -> Result<NearestNodeDto, AppError> {
+        let index = self.node_index(area_id).await?;
+        let (node, distance) = index
+            .nearest(x, y)
+            .ok_or(AppError::Coded(ErrorCode::NodeNotFound))?;
+
+        Ok(NearestNodeDto {
+            node_id: node.id,
+            area_id: node.area_id,
+            x: node.x,
+            y: node.y,
+            distance,
+        })
+    }
+
+    // 作成済みのノードインデックスの数とノード数の合計
+    pub fn node_index_stats(&self) -> (usize, usize) {
+        let indexes = self.node_indexes.read().unwrap();
+        (
+            indexes.len(),
+            indexes.values().map(|index| index.len()).sum(),
+        )
+    }
+
+    async fn node_index(&self, area_id: Option<i32>) -> Result<Arc<GridIndex>, AppError> {
+        if let Some(index) = self.node_indexes.read().unwrap().get(&area_id) {
+            return Ok(index.clone());
+        }
+
+        let nodes = self.repository.get_all_nodes(area_id).await?;
+        let index = Arc::new(GridIndex::new(nodes));
+        // 存在しないエリアの空インデックスはキャッシュしない
+        if !index.is_empty() {
+            self.node_indexes
+                .write()
+                .unwrap()
+                .insert(area_id, index.clone());
+        }
+
+        Ok(index)
+    }
 }
@@ -1,5 +1,9 @@
 pub mod auth_service;
+pub mod auto_dispatch;
+pub mod dispatch_offer;
+pub mod dispatch_strategy;
 pub mod dto;
 pub mod map_service;
 pub mod order_service;
+pub mod sla_escalation;
 pub mod tow_truck_service;
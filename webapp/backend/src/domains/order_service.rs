@@ -1,14 +1,38 @@
 use chrono::{DateTime, Utc};
 
-use image::{GenericImageView, ImageBuffer};
-
-use std::time::Instant;
-
 use super::{
-    auth_service::AuthRepository, dto::order::OrderDto, map_service::MapRepository,
+    auth_service::AuthRepository,
+    dispatch_offer::OfferConfig,
+    dto::{
+        driver::DriverJobDto,
+        order::{
+            EtaDto, OfferEventDto, OrderAssignmentDto, OrderCancellationDto, OrderDto,
+            OrderEscalationEventDto, OrderEventDto,
+        },
+        tow_truck::{RouteSegmentDto, TowTruckEventDto},
+    },
+    map_service::{load_area_graph, MapRepository},
+    sla_escalation::SlaConfig,
     tow_truck_service::TowTruckRepository,
 };
-use crate::{errors::AppError, models::order::Order};
+use crate::{
+    errors::{AppError, ErrorCode},
+    infrastructure::{
+        db::env_or,
+        events::{order_event_hub, tow_truck_event_hub},
+        metrics::metrics,
+    },
+    models::{
+        order::{
+            AssignmentChange, NewOrderCancellation, Order, OrderAssignment, TowTruckWorkload,
+            UnescalatedOrder,
+        },
+        user::Dispatcher,
+    },
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{instrument, warn};
 
 pub trait OrderRepository {
     async fn find_order_by_id(&self, id: i32) -> Result<Order, AppError>;
@@ -17,8 +41,7 @@ pub trait OrderRepository {
         &self,
         page: i32,
         page_size: i32,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
+        sort: OrderSort<'_>,
         status: Option<String>,
         area: Option<i32>,
     ) -> Result<Vec<Order>, AppError>;
@@ -28,18 +51,166 @@ pub trait OrderRepository {
         node_id: i32,
         car_value: f64,
     ) -> Result<(), AppError>;
-    async fn update_order_dispatched(
+    // completed_orders への記録、依頼の配車、割り当て履歴、レッカー車の状態を同じトランザクションで更新する
+    async fn dispatch_order(
         &self,
-        id: i32,
+        order_id: i32,
         dispatcher_id: i32,
         tow_truck_id: i32,
+        completed_time: DateTime<Utc>,
     ) -> Result<(), AppError>;
-    async fn create_completed_order(
+    async fn find_dispatched_orders(
         &self,
-        order_id: i32,
-        tow_truck_id: i32,
-        completed_time: DateTime<Utc>,
+        area_id: Option<i32>,
+        tow_truck_ids: Option<&[i32]>,
+    ) -> Result<Vec<Order>, AppError>;
+    async fn find_pending_orders(&self, area_id: i32) -> Result<Vec<Order>, AppError>;
+    async fn get_tow_truck_workloads(
+        &self,
+        tow_truck_ids: &[i32],
+        since: DateTime<Utc>,
+    ) -> Result<Vec<TowTruckWorkload>, AppError>;
+    // assignments は (order_id, tow_truck_id)。1件でも割り当てられない場合は何も変更しない
+    // dispatcher_id が None の場合は自動配車
+    async fn dispatch_orders(
+        &self,
+        area_id: i32,
+        dispatcher_id: Option<i32>,
+        assignments: &[(i32, i32)],
+        dispatched_time: DateTime<Utc>,
+    ) -> Result<(), AppError>;
+    async fn find_unescalated_pending_orders(
+        &self,
+        received_before: DateTime<Utc>,
+    ) -> Result<Vec<UnescalatedOrder>, AppError>;
+    async fn mark_orders_escalated(
+        &self,
+        order_ids: &[i32],
+        escalated_at: DateTime<Utc>,
     ) -> Result<(), AppError>;
+    // 取り消しの記録と同じトランザクションで、割り当て済みのレッカー車を空きに戻す。戻したレッカー車の ID を返す
+    async fn cancel_order(
+        &self,
+        order_id: i32,
+        cancellation: &NewOrderCancellation,
+    ) -> Result<Option<i32>, AppError>;
+    // 配車済みの依頼を tow_truck_id に付け替える。None の場合は割り当てを外して配車待ちに戻す
+    // 元のレッカー車は空きに戻し、その ID を返す
+    async fn change_assignment(
+        &self,
+        order_id: i32,
+        area_id: i32,
+        tow_truck_id: Option<i32>,
+        change: &AssignmentChange,
+    ) -> Result<i32, AppError>;
+    async fn get_order_assignments(&self, order_id: i32) -> Result<Vec<OrderAssignment>, AppError>;
+    async fn find_current_assignment(
+        &self,
+        order_id: i32,
+    ) -> Result<Option<OrderAssignment>, AppError>;
+    // 応答待ちの申し出だけを更新する。更新できなければ false
+    async fn respond_to_offer(
+        &self,
+        assignment_id: i32,
+        offer_status: &str,
+        responded_at: DateTime<Utc>,
+    ) -> Result<bool, AppError>;
+    // 配車済みの依頼の現在の割り当てのうち、辞退・期限切れになったものと offered_before より前から応答のないもの
+    async fn find_unaccepted_offers(
+        &self,
+        offered_before: DateTime<Utc>,
+    ) -> Result<Vec<OrderAssignment>, AppError>;
+    // 承諾済みの現在の割り当てを status の段階に進める。直前の段階まで進んでいなければ false
+    // completed の場合は依頼も完了にする
+    async fn advance_job(
+        &self,
+        assignment_id: i32,
+        order_id: i32,
+        status: &str,
+        at: DateTime<Utc>,
+    ) -> Result<bool, AppError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct EtaConfig {
+    // 1秒あたりに進む辺の重みの平均
+    pub average_speed: f64,
+}
+
+const DEFAULT_ETA_AVERAGE_SPEED: f64 = 10.0;
+
+impl EtaConfig {
+    pub fn from_env() -> Self {
+        let average_speed = env_or("ETA_AVERAGE_SPEED", DEFAULT_ETA_AVERAGE_SPEED);
+        // 0 以下だと到着時刻を計算できないので既定値を使う
+        let average_speed = if average_speed.is_finite() && average_speed > 0.0 {
+            average_speed
+        } else {
+            warn!(
+                average_speed,
+                "ETA_AVERAGE_SPEED が不正なので既定値を使います"
+            );
+            DEFAULT_ETA_AVERAGE_SPEED
+        };
+
+        EtaConfig { average_speed }
+    }
+}
+
+// 依頼一覧の並び順
+#[derive(Debug)]
+pub enum OrderSort<'a> {
+    // sort_by に指定された列
+    Column {
+        sort_by: String,
+        sort_order: Option<String>,
+    },
+    // 優先度の点数。sort_order の指定がなければ高い順
+    Priority {
+        config: &'a PriorityConfig,
+        now: DateTime<Utc>,
+        sort_order: Option<String>,
+    },
+}
+
+// 一覧の既定の並び順に使う優先度の点数
+#[derive(Debug, Clone)]
+pub struct PriorityConfig {
+    // 車両価格 1 あたりの点数
+    pub car_value_weight: f64,
+    // 待ち時間 1 分あたりの点数
+    pub wait_weight: f64,
+    // 車両価格がしきい値以上の依頼に加える点数
+    pub high_value_threshold: f64,
+    pub high_value_bonus: f64,
+    // SLA を超えてエスカレーションされた依頼に加える点数
+    pub escalation_bonus: f64,
+}
+
+impl PriorityConfig {
+    pub fn from_env() -> Self {
+        PriorityConfig {
+            car_value_weight: env_or("PRIORITY_CAR_VALUE_WEIGHT", 0.1),
+            wait_weight: env_or("PRIORITY_WAIT_WEIGHT", 10.0),
+            high_value_threshold: env_or("PRIORITY_HIGH_VALUE_THRESHOLD", 10000.0),
+            high_value_bonus: env_or("PRIORITY_HIGH_VALUE_BONUS", 300.0),
+            escalation_bonus: env_or("PRIORITY_ESCALATION_BONUS", 1000.0),
+        }
+    }
+
+    // 待ち時間は完了した依頼なら完了時刻まで。並び替えの SQL と同じ式
+    pub fn score(&self, order: &Order, now: DateTime<Utc>) -> f64 {
+        let waited_secs = (order.completed_time.unwrap_or(now) - order.order_time).num_seconds();
+        let mut score =
+            self.car_value_weight * order.car_value + self.wait_weight * waited_secs as f64 / 60.0;
+        if order.car_value >= self.high_value_threshold {
+            score += self.high_value_bonus;
+        }
+        if order.escalated_at.is_some() {
+            score += self.escalation_bonus;
+        }
+        score
+    }
 }
 
 #[derive(Debug)]
@@ -53,6 +224,11 @@ pub struct OrderService<
     tow_truck_repository: U,
     auth_repository: V,
     map_repository: W,
+    eta_config: EtaConfig,
+    priority_config: PriorityConfig,
+    offer_config: OfferConfig,
+    // 依頼ごとに最後に配信した到着見込みの距離。変化したときだけ配信する
+    last_eta_distances: Mutex<HashMap<i32, i32>>,
 }
 
 impl<
@@ -67,279 +243,998 @@ impl<
         tow_truck_repository: U,
         auth_repository: V,
         map_repository: W,
+        eta_config: EtaConfig,
+        priority_config: PriorityConfig,
+        offer_config: OfferConfig,
     ) -> Self {
         OrderService {
             order_repository,
             tow_truck_repository,
             auth_repository,
             map_repository,
+            eta_config,
+            priority_config,
+            offer_config,
+            last_eta_distances: Mutex::new(HashMap::new()),
         }
     }
 
+    #[instrument(skip(self))]
     pub async fn update_order_status(&self, order_id: i32, status: &str) -> Result<(), AppError> {
         self.order_repository
             .update_order_status(order_id, status)
-            .await
+            .await?;
+        if status != "dispatched" {
+            self.last_eta_distances.lock().unwrap().remove(&order_id);
+        }
+        self.publish_order_event(order_id, "status").await
     }
 
+    #[instrument(skip(self))]
     pub async fn get_order_by_id(&self, id: i32) -> Result<OrderDto, AppError> {
+        let order = self
+            .order_repository
+            .find_order_by_id(id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
 
-        let order_start = Instant::now();
+        let eta = self.estimate_eta(&order).await?;
+        let mut dto = self.build_order_dto(order).await?;
+        dto.eta = eta;
 
-        let order = self.order_repository.find_order_by_id(id).await?;
+        Ok(dto)
+    }
 
-        let order_duration0 = order_start.elapsed();
-        println!("order_duration0 时间间隔: {:?}", order_duration0);
+    // レッカー車の位置が変わったときに、割り当て先の依頼の到着見込みを配信し直す
+    #[instrument(skip(self))]
+    pub async fn refresh_etas_for_tow_trucks(&self, tow_truck_ids: &[i32]) -> Result<(), AppError> {
+        let orders = self
+            .order_repository
+            .find_dispatched_orders(None, Some(tow_truck_ids))
+            .await?;
+        for order in orders {
+            self.publish_eta(&order).await?;
+        }
+
+        Ok(())
+    }
 
-        let client_username = self
-            .auth_repository
-            .find_user_by_id(order.client_id)
+    // 辺の重みが変わったときに、そのエリアの配車済みの依頼の到着見込みを配信し直す
+    #[instrument(skip(self))]
+    pub async fn refresh_etas_for_node(&self, node_id: i32) -> Result<(), AppError> {
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(node_id)
             .await
+            .map_err(|e| AppError::from(e).not_found_as(ErrorCode::NodeNotFound))?;
+        let orders = self
+            .order_repository
+            .find_dispatched_orders(Some(area_id), None)
+            .await?;
+        if orders.is_empty() {
+            return Ok(());
+        }
+
+        for order in orders {
+            self.publish_eta(&order).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn publish_eta(&self, order: &Order) -> Result<(), AppError> {
+        let eta = match self.estimate_eta(order).await? {
+            Some(eta) => eta,
+            None => return Ok(()),
+        };
+
+        let previous = self
+            .last_eta_distances
+            .lock()
             .unwrap()
-            .unwrap()
-            .username;
+            .insert(order.id, eta.distance);
+        if previous == Some(eta.distance) {
+            return Ok(());
+        }
+
+        order_event_hub().publish(
+            order.client_id,
+            "eta",
+            &OrderEventDto {
+                order_id: order.id,
+                status: order.status.clone(),
+                tow_truck_id: order.tow_truck_id,
+                eta: Some(eta),
+                timestamp: Utc::now(),
+            },
+        );
+
+        Ok(())
+    }
 
-        let order_duration1 = order_start.elapsed();
-        println!("order_duration1 时间间隔: {:?}", order_duration1);
+    // 配車済みの依頼だけが対象。レッカー車から依頼地点に到達できない場合は None
+    async fn estimate_eta(&self, order: &Order) -> Result<Option<EtaDto>, AppError> {
+        let tow_truck_id = match order.tow_truck_id {
+            Some(tow_truck_id) if order.status == "dispatched" => tow_truck_id,
+            _ => return Ok(None),
+        };
+        let tow_truck = match self
+            .tow_truck_repository
+            .find_tow_truck_by_id(tow_truck_id)
+            .await?
+        {
+            Some(tow_truck) => tow_truck,
+            None => return Ok(None),
+        };
+
+        let graph = load_area_graph(&self.map_repository, tow_truck.area_id).await?;
+        let distance = match graph.shortest_route(tow_truck.node_id, order.node_id) {
+            Some((distance, _)) => distance,
+            None => {
+                warn!(
+                    order_id = order.id,
+                    tow_truck_id, "依頼地点に到達できません"
+                );
+                return Ok(None);
+            }
+        };
+
+        let seconds = (distance as f64 / self.eta_config.average_speed).ceil() as i64;
+        Ok(Some(EtaDto {
+            distance,
+            seconds,
+            estimated_arrival: Utc::now() + chrono::Duration::seconds(seconds),
+        }))
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_paginated_orders(
+        &self,
+        page: i32,
+        page_size: i32,
+        sort_by: Option<String>,
+        sort_order: Option<String>,
+        status: Option<String>,
+        area: Option<i32>,
+    ) -> Result<Vec<OrderDto>, AppError> {
+        let sort = match sort_by {
+            Some(sort_by) if sort_by != "priority" => OrderSort::Column {
+                sort_by,
+                sort_order,
+            },
+            _ => OrderSort::Priority {
+                config: &self.priority_config,
+                now: Utc::now(),
+                sort_order,
+            },
+        };
+        let orders = self
+            .order_repository
+            .get_paginated_orders(page, page_size, sort, status, area)
+            .await?;
+
+        let mut results = Vec::new();
+
+        for order in orders {
+            results.push(self.build_order_dto(order).await?);
+        }
+
+        Ok(results)
+    }
+
+    async fn find_username(&self, user_id: i32) -> Result<String, AppError> {
+        match self.auth_repository.find_user_by_id(user_id).await? {
+            Some(user) => Ok(user.username),
+            None => Err(AppError::Coded(ErrorCode::UserNotFound)),
+        }
+    }
+
+    async fn build_order_dto(&self, order: Order) -> Result<OrderDto, AppError> {
+        let priority = self.priority_config.score(&order, Utc::now());
+        let client_username = self.find_username(order.client_id).await?;
 
         let dispatcher = match order.dispatcher_id {
-            Some(dispatcher_id) => self
-                .auth_repository
-                .find_dispatcher_by_id(dispatcher_id)
-                .await
-                .unwrap(),
+            Some(dispatcher_id) => {
+                self.auth_repository
+                    .find_dispatcher_by_id(dispatcher_id)
+                    .await?
+            }
             None => None,
         };
 
-        let order_duration2 = order_start.elapsed();
-        println!("order_duration2 时间间隔: {:?}", order_duration2);
-
         let (dispatcher_user_id, dispatcher_username) = match dispatcher {
             Some(dispatcher) => (
                 Some(dispatcher.user_id),
-                Some(
-                    self.auth_repository
-                        .find_user_by_id(dispatcher.user_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .username,
-                ),
+                Some(self.find_username(dispatcher.user_id).await?),
             ),
             None => (None, None),
         };
 
-        let order_duration3 = order_start.elapsed();
-        println!("order_duration3 时间间隔: {:?}", order_duration3);
-
         let tow_truck = match order.tow_truck_id {
-            Some(tow_truck_id) => self
-                .tow_truck_repository
-                .find_tow_truck_by_id(tow_truck_id)
-                .await
-                .unwrap(),
+            Some(tow_truck_id) => {
+                self.tow_truck_repository
+                    .find_tow_truck_by_id(tow_truck_id)
+                    .await?
+            }
             None => None,
         };
 
-        let order_duration4 = order_start.elapsed();
-        println!("order_duration4 时间间隔: {:?}", order_duration4);
-
         let (driver_user_id, driver_username) = match tow_truck {
             Some(tow_truck) => (
                 Some(tow_truck.driver_id),
-                Some(
-                    self.auth_repository
-                        .find_user_by_id(tow_truck.driver_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .username,
-                ),
+                Some(self.find_username(tow_truck.driver_id).await?),
             ),
             None => (None, None),
         };
 
-        let order_duration5 = order_start.elapsed();
-        println!("order_duration5 时间间隔: {:?}", order_duration5);
-
         let area_id = self
             .map_repository
             .get_area_id_by_node_id(order.node_id)
             .await
-            .unwrap();
-
-        let order_duration6 = order_start.elapsed();
-        println!("order_duration6 时间间隔: {:?}", order_duration6);
+            .map_err(|e| AppError::from(e).not_found_as(ErrorCode::NodeNotFound))?;
 
         Ok(OrderDto {
             id: order.id,
             client_id: order.client_id,
             client_username: Some(client_username),
+            dispatcher_id: order.dispatcher_id,
             dispatcher_user_id,
             dispatcher_username,
+            tow_truck_id: order.tow_truck_id,
             driver_user_id,
             driver_username,
             area_id,
-            dispatcher_id: order.dispatcher_id,
-            tow_truck_id: order.tow_truck_id,
             status: order.status,
             node_id: order.node_id,
             car_value: order.car_value,
             order_time: order.order_time,
             completed_time: order.completed_time,
+            priority,
+            escalated_at: order.escalated_at,
+            eta: None,
         })
     }
 
-    pub async fn get_paginated_orders(
+    #[instrument(skip(self))]
+    pub async fn create_client_order(
         &self,
-        page: i32,
-        page_size: i32,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
-        status: Option<String>,
-        area: Option<i32>,
-    ) -> Result<Vec<OrderDto>, AppError> {
-        let orders = self
+        client_id: i32,
+        node_id: i32,
+        car_value: f64,
+    ) -> Result<(), AppError> {
+        // 存在しない client_id / node_id は外部キー制約違反として 400 になる
+        self.order_repository
+            .create_order(client_id, node_id, car_value)
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn create_dispatcher_order(
+        &self,
+        order_id: i32,
+        dispatcher_id: i32,
+        tow_truck_id: i32,
+        order_time: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        // 解放されていない completed_orders の行のレッカー車は重複できないので、重複はレッカー車が割り当て済みであることを表す
+        self.order_repository
+            .dispatch_order(order_id, dispatcher_id, tow_truck_id, order_time)
+            .await
+            .map_err(|e| match e.code() {
+                ErrorCode::DuplicateEntry => AppError::Coded(ErrorCode::TowTruckUnavailable),
+                _ => e,
+            })?;
+
+        // 配車担当者は自分のエリアの依頼だけを扱うので、エリアは依頼のノードから引く
+        let order = self
             .order_repository
-            .get_paginated_orders(page, page_size, sort_by, sort_order, status, area)
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(order.node_id)
             .await?;
+        metrics().inc_dispatch(area_id);
+        tow_truck_event_hub().publish(
+            area_id,
+            "status",
+            &TowTruckEventDto {
+                tow_truck_id,
+                area_id,
+                node_id: None,
+                status: Some("busy".to_string()),
+                timestamp: Utc::now(),
+            },
+        );
 
-        let mut results = Vec::new();
+        self.publish_order_event(order_id, "dispatched").await?;
+        self.publish_offer_event(order_id, tow_truck_id, "offer")
+            .await
+    }
 
-        for order in orders {
-            let client_username = self
+    // 割り当て計画をまとめて受け入れる。1件でも割り当てられなくなっていれば何も変更しない
+    #[instrument(skip(self, assignments))]
+    pub async fn accept_assignment_plan(
+        &self,
+        dispatcher: &Dispatcher,
+        assignments: &[(i32, i32)],
+    ) -> Result<(), AppError> {
+        self.dispatch_orders(dispatcher.area_id, Some(dispatcher.id), assignments)
+            .await
+    }
+
+    // 自動配車。配車担当者は記録しない
+    #[instrument(skip(self))]
+    pub async fn auto_dispatch_order(
+        &self,
+        area_id: i32,
+        order_id: i32,
+        tow_truck_id: i32,
+    ) -> Result<(), AppError> {
+        self.dispatch_orders(area_id, None, &[(order_id, tow_truck_id)])
+            .await
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_pending_orders(&self, area_id: i32) -> Result<Vec<Order>, AppError> {
+        self.order_repository.find_pending_orders(area_id).await
+    }
+
+    async fn dispatch_orders(
+        &self,
+        area_id: i32,
+        dispatcher_id: Option<i32>,
+        assignments: &[(i32, i32)],
+    ) -> Result<(), AppError> {
+        self.order_repository
+            .dispatch_orders(area_id, dispatcher_id, assignments, Utc::now())
+            .await
+            .map_err(|e| match e.code() {
+                ErrorCode::DuplicateEntry => AppError::Coded(ErrorCode::TowTruckUnavailable),
+                _ => e,
+            })?;
+
+        for &(order_id, tow_truck_id) in assignments {
+            metrics().inc_dispatch(area_id);
+            tow_truck_event_hub().publish(
+                area_id,
+                "status",
+                &TowTruckEventDto {
+                    tow_truck_id,
+                    area_id,
+                    node_id: None,
+                    status: Some("busy".to_string()),
+                    timestamp: Utc::now(),
+                },
+            );
+            self.publish_order_event(order_id, "dispatched").await?;
+            self.publish_offer_event(order_id, tow_truck_id, "offer")
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // 依頼者本人か、依頼地点のエリアの配車担当者だけが取り消せる
+    #[instrument(skip(self))]
+    pub async fn cancel_order(
+        &self,
+        order_id: i32,
+        user_id: i32,
+        role: &str,
+        reason: &str,
+        note: Option<String>,
+    ) -> Result<OrderCancellationDto, AppError> {
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(order.node_id)
+            .await?;
+
+        let authorized = match role {
+            "client" => order.client_id == user_id,
+            "dispatcher" => self
                 .auth_repository
-                .find_user_by_id(order.client_id)
-                .await
-                .unwrap()
-                .unwrap()
-                .username;
-
-            let dispatcher = match order.dispatcher_id {
-                Some(dispatcher_id) => self
-                    .auth_repository
-                    .find_dispatcher_by_id(dispatcher_id)
-                    .await
-                    .unwrap(),
-                None => None,
-            };
+                .find_dispatcher_by_user_id(user_id)
+                .await?
+                .is_some_and(|dispatcher| dispatcher.area_id == area_id),
+            _ => false,
+        };
+        if !authorized {
+            return Err(AppError::Coded(ErrorCode::Forbidden));
+        }
 
-            let (dispatcher_user_id, dispatcher_username) = match dispatcher {
-                Some(dispatcher) => (
-                    Some(dispatcher.user_id),
-                    Some(
-                        self.auth_repository
-                            .find_user_by_id(dispatcher.user_id)
-                            .await
-                            .unwrap()
-                            .unwrap()
-                            .username,
-                    ),
-                ),
-                None => (None, None),
-            };
+        let cancellation = NewOrderCancellation {
+            cancelled_by: user_id,
+            cancelled_by_role: role.to_string(),
+            reason: reason.to_string(),
+            note,
+            cancelled_at: Utc::now(),
+        };
+        let released_tow_truck_id = self
+            .order_repository
+            .cancel_order(order_id, &cancellation)
+            .await?;
+        self.last_eta_distances.lock().unwrap().remove(&order_id);
 
-            let tow_truck = match order.tow_truck_id {
-                Some(tow_truck_id) => self
-                    .tow_truck_repository
-                    .find_tow_truck_by_id(tow_truck_id)
+        if let Some(tow_truck_id) = released_tow_truck_id {
+            tow_truck_event_hub().publish(
+                area_id,
+                "status",
+                &TowTruckEventDto {
+                    tow_truck_id,
+                    area_id,
+                    node_id: None,
+                    status: Some("available".to_string()),
+                    timestamp: cancellation.cancelled_at,
+                },
+            );
+        }
+        self.publish_order_event(order_id, "cancelled").await?;
+
+        Ok(OrderCancellationDto {
+            order_id,
+            reason: cancellation.reason,
+            note: cancellation.note,
+            cancelled_by: cancellation.cancelled_by,
+            cancelled_by_role: cancellation.cancelled_by_role,
+            released_tow_truck_id,
+            cancelled_at: cancellation.cancelled_at,
+        })
+    }
+
+    // 故障などで配車済みの依頼を別のレッカー車に付け替える。tow_truck_id が None なら配車待ちに戻す
+    // 依頼地点のエリアの配車担当者だけが変更できる
+    #[instrument(skip(self))]
+    pub async fn change_assignment(
+        &self,
+        dispatcher: &Dispatcher,
+        order_id: i32,
+        tow_truck_id: Option<i32>,
+        reason: &str,
+    ) -> Result<Vec<OrderAssignmentDto>, AppError> {
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(order.node_id)
+            .await?;
+        if dispatcher.area_id != area_id {
+            return Err(AppError::Coded(ErrorCode::Forbidden));
+        }
+
+        let change = AssignmentChange {
+            dispatcher_id: Some(dispatcher.id),
+            expected_tow_truck_id: None,
+            reason: reason.to_string(),
+            changed_at: Utc::now(),
+        };
+        self.apply_assignment_change(order_id, area_id, tow_truck_id, &change)
+            .await?;
+
+        self.get_order_assignments(order_id).await
+    }
+
+    async fn apply_assignment_change(
+        &self,
+        order_id: i32,
+        area_id: i32,
+        tow_truck_id: Option<i32>,
+        change: &AssignmentChange,
+    ) -> Result<(), AppError> {
+        let released_tow_truck_id = self
+            .order_repository
+            .change_assignment(order_id, area_id, tow_truck_id, change)
+            .await
+            .map_err(|e| match e.code() {
+                ErrorCode::DuplicateEntry => AppError::Coded(ErrorCode::TowTruckUnavailable),
+                _ => e,
+            })?;
+        self.last_eta_distances.lock().unwrap().remove(&order_id);
+
+        let statuses = std::iter::once((released_tow_truck_id, "available"))
+            .chain(tow_truck_id.map(|tow_truck_id| (tow_truck_id, "busy")));
+        for (tow_truck_id, status) in statuses {
+            tow_truck_event_hub().publish(
+                area_id,
+                "status",
+                &TowTruckEventDto {
+                    tow_truck_id,
+                    area_id,
+                    node_id: None,
+                    status: Some(status.to_string()),
+                    timestamp: change.changed_at,
+                },
+            );
+        }
+        match tow_truck_id {
+            Some(tow_truck_id) => {
+                self.publish_order_event(order_id, "reassigned").await?;
+                let order = self.order_repository.find_order_by_id(order_id).await?;
+                self.publish_eta(&order).await?;
+                self.publish_offer_event(order_id, tow_truck_id, "offer")
                     .await
-                    .unwrap(),
-                None => None,
+            }
+            None => self.publish_order_event(order_id, "unassigned").await,
+        }
+    }
+
+    // 割り当てられたレッカー車のドライバーが申し出を承諾・辞退する。申し出たレッカー車の ID を返す
+    // 辞退した依頼の付け替えは dispatch_offer::fall_back で行う
+    #[instrument(skip(self))]
+    pub async fn respond_to_offer(
+        &self,
+        driver_id: i32,
+        order_id: i32,
+        accept: bool,
+    ) -> Result<i32, AppError> {
+        let assignment = self
+            .order_repository
+            .find_current_assignment(order_id)
+            .await?
+            .ok_or(AppError::Coded(ErrorCode::OfferNotPending))?;
+        let driver_matches = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(assignment.tow_truck_id)
+            .await?
+            .is_some_and(|tow_truck| tow_truck.driver_id == driver_id);
+        if !driver_matches {
+            return Err(AppError::Coded(ErrorCode::Forbidden));
+        }
+
+        let offer_status = if accept { "accepted" } else { "declined" };
+        if !self
+            .order_repository
+            .respond_to_offer(assignment.id, offer_status, Utc::now())
+            .await?
+        {
+            return Err(AppError::Coded(ErrorCode::OfferNotPending));
+        }
+        metrics().inc_dispatch_offer(offer_status);
+
+        if accept {
+            self.publish_order_event(order_id, "accepted").await?;
+        }
+        Ok(assignment.tow_truck_id)
+    }
+
+    // 期限を過ぎた申し出を期限切れにし、付け替えが必要な割り当てを返す
+    #[instrument(skip(self))]
+    pub async fn expire_offers(&self) -> Result<Vec<OrderAssignment>, AppError> {
+        let now = Utc::now();
+        let timeout = match self.offer_config.timeout() {
+            Some(timeout) => timeout,
+            None => return Ok(Vec::new()),
+        };
+        let mut offers = self
+            .order_repository
+            .find_unaccepted_offers(now - timeout)
+            .await?;
+
+        let mut expired = Vec::new();
+        for offer in &mut offers {
+            if offer.offer_status != "offered" {
+                continue;
+            }
+            // 確認の間にドライバーが応答した申し出は付け替えない
+            if !self
+                .order_repository
+                .respond_to_offer(offer.id, "expired", now)
+                .await?
+            {
+                continue;
+            }
+            metrics().inc_dispatch_offer("expired");
+            offer.offer_status = "expired".to_string();
+            expired.push((offer.order_id, offer.tow_truck_id));
+        }
+        for (order_id, tow_truck_id) in expired {
+            self.publish_offer_event(order_id, tow_truck_id, "offer_expired")
+                .await?;
+        }
+
+        offers.retain(|offer| offer.offer_status != "offered");
+        Ok(offers)
+    }
+
+    // ドライバーのレッカー車に割り当てられている配車済みの依頼と、そこまでの経路
+    #[instrument(skip(self))]
+    pub async fn get_driver_jobs(&self, driver_id: i32) -> Result<Vec<DriverJobDto>, AppError> {
+        let tow_trucks = self
+            .tow_truck_repository
+            .find_tow_trucks_by_driver_id(driver_id)
+            .await?;
+        if tow_trucks.is_empty() {
+            return Ok(Vec::new());
+        }
+        let tow_truck_ids: Vec<i32> = tow_trucks.iter().map(|tow_truck| tow_truck.id).collect();
+        let orders = self
+            .order_repository
+            .find_dispatched_orders(None, Some(&tow_truck_ids))
+            .await?;
+
+        let mut jobs = Vec::with_capacity(orders.len());
+        for order in orders {
+            let (tow_truck, assignment) = match (
+                order.tow_truck_id.and_then(|tow_truck_id| {
+                    tow_trucks
+                        .iter()
+                        .find(|tow_truck| tow_truck.id == tow_truck_id)
+                }),
+                self.order_repository
+                    .find_current_assignment(order.id)
+                    .await?,
+            ) {
+                // 辞退・期限切れで付け替えを待っているものは除く
+                (Some(tow_truck), Some(assignment))
+                    if matches!(assignment.offer_status.as_str(), "offered" | "accepted") =>
+                {
+                    (tow_truck, assignment)
+                }
+                _ => continue,
             };
 
-            let (driver_user_id, driver_username) = match tow_truck {
-                Some(tow_truck) => (
-                    Some(tow_truck.driver_id),
-                    Some(
-                        self.auth_repository
-                            .find_user_by_id(tow_truck.driver_id)
-                            .await
-                            .unwrap()
-                            .unwrap()
-                            .username,
-                    ),
-                ),
-                None => (None, None),
+            let graph = load_area_graph(&self.map_repository, tow_truck.area_id).await?;
+            let route = match graph.shortest_route(tow_truck.node_id, order.node_id) {
+                Some((distance, node_ids)) => RouteSegmentDto {
+                    from_node_id: tow_truck.node_id,
+                    to_node_id: order.node_id,
+                    distance: Some(distance),
+                    node_ids,
+                },
+                // 到達できない場合は端点だけを返す
+                None => RouteSegmentDto {
+                    from_node_id: tow_truck.node_id,
+                    to_node_id: order.node_id,
+                    distance: None,
+                    node_ids: vec![tow_truck.node_id, order.node_id],
+                },
             };
 
-            let order_area_id = self
-                .map_repository
-                .get_area_id_by_node_id(order.node_id)
-                .await
-                .unwrap();
-
-            results.push(OrderDto {
-                id: order.id,
-                client_id: order.client_id,
-                client_username: Some(client_username),
-                dispatcher_id: order.dispatcher_id,
-                dispatcher_user_id,
-                dispatcher_username,
-                tow_truck_id: order.tow_truck_id,
-                driver_user_id,
-                driver_username,
-                area_id: order_area_id,
-                status: order.status,
+            let offer_expires_at = if assignment.offer_status == "offered" {
+                self.offer_config.expires_at(assignment.assigned_at)
+            } else {
+                None
+            };
+            jobs.push(DriverJobDto {
+                order_id: order.id,
+                tow_truck_id: tow_truck.id,
+                status: assignment.job_status().to_string(),
                 node_id: order.node_id,
                 car_value: order.car_value,
                 order_time: order.order_time,
-                completed_time: order.completed_time,
+                offer_expires_at,
+                route,
             });
         }
 
-        Ok(results)
+        Ok(jobs)
     }
 
-    pub async fn create_client_order(
+    // ドライバーが作業の段階を報告する。承諾済みの依頼を en_route, on_site, completed の順にだけ進められる
+    #[instrument(skip(self))]
+    pub async fn advance_job(
         &self,
-        client_id: i32,
-        node_id: i32,
-        car_value: f64,
+        driver_id: i32,
+        order_id: i32,
+        status: &str,
     ) -> Result<(), AppError> {
-        match self
+        let assignment = self
             .order_repository
-            .create_order(client_id, node_id, car_value)
-            .await
+            .find_current_assignment(order_id)
+            .await?
+            .ok_or(AppError::Coded(ErrorCode::InvalidJobTransition))?;
+        let tow_truck = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(assignment.tow_truck_id)
+            .await?
+            .filter(|tow_truck| tow_truck.driver_id == driver_id)
+            .ok_or(AppError::Coded(ErrorCode::Forbidden))?;
+
+        // 段階は en_route → on_site → completed の順にだけ進める
+        let name = match status {
+            "en_route" => "en_route",
+            "on_site" => "on_site",
+            "completed" => "completed",
+            _ => return Err(AppError::Coded(ErrorCode::InvalidJobTransition)),
+        };
+        if assignment.next_job_status() != Some(name) {
+            return Err(AppError::Coded(ErrorCode::InvalidJobTransition));
+        }
+
+        // 同時に進められた場合に備えて、更新時にも直前の段階を確かめる
+        let at = Utc::now();
+        if !self
+            .order_repository
+            .advance_job(assignment.id, order_id, name, at)
+            .await?
         {
-            Ok(_) => Ok(()),
-            Err(_) => Err(AppError::BadRequest),
+            return Err(AppError::Coded(ErrorCode::InvalidJobTransition));
+        }
+        if name == "completed" {
+            self.last_eta_distances.lock().unwrap().remove(&order_id);
+            tow_truck_event_hub().publish(
+                tow_truck.area_id,
+                "status",
+                &TowTruckEventDto {
+                    tow_truck_id: tow_truck.id,
+                    area_id: tow_truck.area_id,
+                    node_id: None,
+                    status: Some("available".to_string()),
+                    timestamp: at,
+                },
+            );
         }
+
+        self.publish_order_event(order_id, name).await
     }
 
-    pub async fn create_dispatcher_order(
+    // これまでにこの依頼の申し出を辞退した、または期限切れにしたレッカー車
+    #[instrument(skip(self))]
+    pub async fn is_order_dispatched(&self, order_id: i32) -> Result<bool, AppError> {
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+
+        Ok(order.status == "dispatched")
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_rejected_tow_truck_ids(&self, order_id: i32) -> Result<Vec<i32>, AppError> {
+        let assignments = self
+            .order_repository
+            .get_order_assignments(order_id)
+            .await?;
+
+        Ok(assignments
+            .into_iter()
+            .filter(|assignment| matches!(assignment.offer_status.as_str(), "declined" | "expired"))
+            .map(|assignment| assignment.tow_truck_id)
+            .collect())
+    }
+
+    // 辞退・期限切れになった tow_truck_id の割り当てを next_tow_truck_id に付け替える。None なら配車待ちに戻す
+    #[instrument(skip(self))]
+    pub async fn fall_back_offer(
         &self,
         order_id: i32,
-        dispatcher_id: i32,
         tow_truck_id: i32,
-        order_time: DateTime<Utc>,
+        next_tow_truck_id: Option<i32>,
     ) -> Result<(), AppError> {
-        let create_start = Instant::now();
-        if (self
+        let assignment = self
+            .order_repository
+            .find_current_assignment(order_id)
+            .await?
+            .filter(|assignment| assignment.tow_truck_id == tow_truck_id)
+            .ok_or(AppError::Coded(ErrorCode::OfferNotPending))?;
+        let reason = match assignment.offer_status.as_str() {
+            "declined" => "offer_declined",
+            "expired" => "offer_expired",
+            _ => return Err(AppError::Coded(ErrorCode::OfferNotPending)),
+        };
+
+        let order = self
             .order_repository
-            .create_completed_order(order_id, tow_truck_id, order_time)
-            .await)
-            .is_err()
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(order.node_id)
+            .await?;
+        let change = AssignmentChange {
+            dispatcher_id: None,
+            expected_tow_truck_id: Some(tow_truck_id),
+            reason: reason.to_string(),
+            changed_at: Utc::now(),
+        };
+        self.apply_assignment_change(order_id, area_id, next_tow_truck_id, &change)
+            .await
+    }
+
+    // 割り当てたレッカー車のドライバーに、申し出とその応答期限を配信する
+    async fn publish_offer_event(
+        &self,
+        order_id: i32,
+        tow_truck_id: i32,
+        name: &'static str,
+    ) -> Result<(), AppError> {
+        let tow_truck = match self
+            .tow_truck_repository
+            .find_tow_truck_by_id(tow_truck_id)
+            .await?
         {
-            return Err(AppError::BadRequest);
+            Some(tow_truck) => tow_truck,
+            None => return Ok(()),
+        };
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+        let assigned_at = self
+            .order_repository
+            .find_current_assignment(order_id)
+            .await?
+            .map_or_else(Utc::now, |assignment| assignment.assigned_at);
+
+        order_event_hub().publish(
+            tow_truck.driver_id,
+            name,
+            &OfferEventDto {
+                order_id,
+                tow_truck_id,
+                node_id: order.node_id,
+                car_value: order.car_value,
+                expires_at: self.offer_config.expires_at(assigned_at),
+                timestamp: Utc::now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_order_assignments(
+        &self,
+        order_id: i32,
+    ) -> Result<Vec<OrderAssignmentDto>, AppError> {
+        let assignments = self
+            .order_repository
+            .get_order_assignments(order_id)
+            .await?;
+
+        Ok(assignments
+            .into_iter()
+            .map(|assignment| OrderAssignmentDto {
+                id: assignment.id,
+                order_id: assignment.order_id,
+                tow_truck_id: assignment.tow_truck_id,
+                assigned_by: assignment.assigned_by,
+                assigned_at: assignment.assigned_at,
+                unassigned_at: assignment.unassigned_at,
+                unassigned_by: assignment.unassigned_by,
+                unassign_reason: assignment.unassign_reason,
+                offer_status: assignment.offer_status,
+                responded_at: assignment.responded_at,
+                en_route_at: assignment.en_route_at,
+                on_site_at: assignment.on_site_at,
+                completed_at: assignment.completed_at,
+            })
+            .collect())
+    }
+
+    // SLA を超えて配車待ちの依頼に印を付け、そのエリアの配車担当者に通知する
+    #[instrument(skip(self, sla))]
+    pub async fn escalate_overdue_orders(&self, sla: &SlaConfig) -> Result<usize, AppError> {
+        let now = Utc::now();
+        let candidates = self
+            .order_repository
+            .find_unescalated_pending_orders(now - chrono::Duration::seconds(sla.min_secs()))
+            .await?;
+        let overdue: Vec<UnescalatedOrder> = candidates
+            .into_iter()
+            .filter(|order| (now - order.order_time).num_seconds() >= sla.secs_for(order.area_id))
+            .collect();
+        if overdue.is_empty() {
+            return Ok(0);
         }
-        let create_duration0 = create_start.elapsed();
-        println!("create_duration0 时间间隔: {:?}", create_duration0);
 
+        let order_ids: Vec<i32> = overdue.iter().map(|order| order.id).collect();
         self.order_repository
-            .update_order_dispatched(order_id, dispatcher_id, tow_truck_id)
+            .mark_orders_escalated(&order_ids, now)
             .await?;
 
-        let create_duration1 = create_start.elapsed();
-        println!("create_duration1 时间间隔: {:?}", create_duration1);
+        for order in &overdue {
+            let sla_secs = sla.secs_for(order.area_id);
+            metrics().inc_order_escalation(order.area_id);
+            warn!(
+                order_id = order.id,
+                area_id = order.area_id,
+                sla_secs,
+                "SLA を超えて配車待ちの依頼をエスカレーションしました"
+            );
+            tow_truck_event_hub().publish(
+                order.area_id,
+                "escalation",
+                &OrderEscalationEventDto {
+                    order_id: order.id,
+                    area_id: order.area_id,
+                    order_time: order.order_time,
+                    sla_secs,
+                    timestamp: now,
+                },
+            );
+        }
 
-        self.tow_truck_repository
-            .update_status(tow_truck_id, "busy")
-            .await?;
+        Ok(overdue.len())
+    }
 
-        let create_duration2 = create_start.elapsed();
-        println!("create_duration2 时间间隔: {:?}", create_duration2);
+    // 依頼者にその時点の依頼の状態を配信する
+    async fn publish_order_event(&self, order_id: i32, name: &'static str) -> Result<(), AppError> {
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
+
+        order_event_hub().publish(
+            order.client_id,
+            name,
+            &OrderEventDto {
+                order_id: order.id,
+                status: order.status,
+                tow_truck_id: order.tow_truck_id,
+                eta: None,
+                timestamp: Utc::now(),
+            },
+        );
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    // 並び替えの SQL (order_repository の get_paginated_orders) と同じ値になる入力
+    fn config() -> PriorityConfig {
+        PriorityConfig {
+            car_value_weight: 0.1,
+            wait_weight: 10.0,
+            high_value_threshold: 10000.0,
+            high_value_bonus: 300.0,
+            escalation_bonus: 1000.0,
+        }
+    }
+
+    fn order(car_value: f64, order_time: DateTime<Utc>) -> Order {
+        Order {
+            id: 1,
+            client_id: 1,
+            dispatcher_id: None,
+            tow_truck_id: None,
+            status: "pending".to_string(),
+            node_id: 1,
+            car_value,
+            order_time,
+            completed_time: None,
+            escalated_at: None,
+        }
+    }
+
+    #[test]
+    fn priority_score_is_pinned() {
+        let config = config();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        // 0.1 * 5000 + 10 * 30分
+        let pending = order(5000.0, now - Duration::minutes(30));
+        assert_eq!(config.score(&pending, now), 800.0);
+
+        // しきい値ちょうどの車両価格と、エスカレーションの加点
+        let mut escalated = order(10000.0, now - Duration::seconds(90));
+        escalated.escalated_at = Some(now);
+        assert_eq!(
+            config.score(&escalated, now),
+            1000.0 + 15.0 + 300.0 + 1000.0
+        );
+
+        // 完了した依頼は完了時刻までの待ち時間
+        let mut completed = order(2000.0, now - Duration::hours(5));
+        completed.completed_time = Some(now - Duration::hours(5) + Duration::minutes(6));
+        assert_eq!(config.score(&completed, now), 200.0 + 60.0);
+    }
+}
@@ -0,0 +1,79 @@
+use super::auth_service::AuthRepository;
+use super::map_service::MapRepository;
+use super::order_service::{OrderRepository, OrderService};
+use super::tow_truck_service::TowTruckRepository;
+use crate::infrastructure::db::env_or;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+// 配車待ちのまま許容する秒数。エリアごとに上書きできる
+#[derive(Debug, Clone)]
+pub struct SlaConfig {
+    default_secs: i64,
+    areas: HashMap<i32, i64>,
+    interval: Duration,
+}
+
+impl SlaConfig {
+    // SLA_BY_AREA は "1=600,2=900" の形式
+    pub fn from_env() -> Self {
+        let mut areas = HashMap::new();
+        for entry in env_or("SLA_BY_AREA", String::new())
+            .split(',')
+            .filter(|entry| !entry.trim().is_empty())
+        {
+            match entry.split_once('=').and_then(|(area_id, secs)| {
+                Some((
+                    area_id.trim().parse::<i32>().ok()?,
+                    secs.trim().parse::<i64>().ok()?,
+                ))
+            }) {
+                Some((area_id, secs)) => {
+                    areas.insert(area_id, secs);
+                }
+                None => warn!(entry, "SLA のエリア指定を読み取れません"),
+            }
+        }
+
+        SlaConfig {
+            default_secs: env_or("SLA_DEFAULT_SECS", 1800),
+            areas,
+            interval: Duration::from_secs(env_or("SLA_CHECK_INTERVAL_SECS", 30).max(1)),
+        }
+    }
+
+    pub fn secs_for(&self, area_id: i32) -> i64 {
+        self.areas
+            .get(&area_id)
+            .copied()
+            .unwrap_or(self.default_secs)
+    }
+
+    // これより新しい依頼はどのエリアでも SLA を超えていない
+    pub fn min_secs(&self) -> i64 {
+        self.areas
+            .values()
+            .copied()
+            .fold(self.default_secs, i64::min)
+    }
+}
+
+pub async fn run_sla_escalation<T, U, V, W>(
+    sla: SlaConfig,
+    order_service: Arc<OrderService<U, T, V, W>>,
+) where
+    T: TowTruckRepository + std::fmt::Debug,
+    U: OrderRepository + std::fmt::Debug,
+    V: AuthRepository + std::fmt::Debug,
+    W: MapRepository + std::fmt::Debug,
+{
+    let mut interval = tokio::time::interval(sla.interval);
+    loop {
+        interval.tick().await;
+        if let Err(err) = order_service.escalate_overdue_orders(&sla).await {
+            warn!(error = ?err, "SLA の確認に失敗しました");
+        }
+    }
+}
@@ -1,15 +1,63 @@
-use super::dto::tow_truck::TowTruckDto;
-use super::map_service::MapRepository;
+use super::dispatch_strategy::{
+    Candidate, DispatchConfig, DispatchStrategy, GraphDistanceOracle, MAX_DISPATCH_DISTANCE,
+};
+use super::dto::order::{AssignmentPlanDto, PlannedAssignmentDto};
+use super::dto::tow_truck::{
+    BatchUpdateLocationResponseDto, LocationHistoryDto, LocationSampleDto, RouteSegmentDto,
+    SkipReason, SkippedLocationDto, TowTruckDto, TowTruckEventDto,
+};
+use super::map_service::{load_area_graph, MapRepository};
 use super::order_service::OrderRepository;
-use crate::errors::AppError;
+use crate::errors::{AppError, ErrorCode};
+use crate::infrastructure::db::env_or;
+use crate::infrastructure::events::tow_truck_event_hub;
+use crate::infrastructure::metrics::metrics;
+use crate::models::assignment::min_cost_assignment;
 use crate::models::graph::Graph;
+use crate::models::location::{LatestLocation, Location, NewLocation};
 use crate::models::tow_truck::TowTruck;
-use std::time::Instant;
+use chrono::{DateTime, Timelike, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{debug, instrument, warn};
 
+// 位置履歴として一度に返すサンプルの上限
+const MAX_LOCATION_HISTORY: i32 = 10000;
+// 端末の時計のずれとして許す、サーバー時刻より先の秒数
+const MAX_CLOCK_SKEW_SECS: i64 = 30;
 
-use std::thread;
-use std::sync::mpsc;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlausibilityAction {
+    Off,
+    Flag,
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+pub struct LocationCheckConfig {
+    pub action: PlausibilityAction,
+    // 1秒あたりに移動できる辺の重みの合計
+    pub max_speed: f64,
+    // 最新位置がこの秒数より古いレッカー車は配車対象から外す。0 の場合は判定しない
+    pub stale_after_secs: i64,
+}
+
+impl LocationCheckConfig {
+    pub fn from_env() -> Self {
+        let action = match env_or("LOCATION_PLAUSIBILITY", "flag".to_string()).as_str() {
+            "off" => PlausibilityAction::Off,
+            "reject" => PlausibilityAction::Reject,
+            _ => PlausibilityAction::Flag,
+        };
 
+        LocationCheckConfig {
+            action,
+            max_speed: env_or("LOCATION_MAX_SPEED", 50.0),
+            // 初期データの位置は古い日時なので、既定では無効にしておく
+            stale_after_secs: env_or("LOCATION_STALE_AFTER_SECS", 0),
+        }
+    }
+}
 
 pub trait TowTruckRepository {
     async fn get_paginated_tow_trucks(
@@ -19,9 +67,27 @@ pub trait TowTruckRepository {
         status: Option<String>,
         area_id: Option<i32>,
     ) -> Result<Vec<TowTruck>, AppError>;
-    async fn update_location(&self, truck_id: i32, node_id: i32) -> Result<(), AppError>;
-    async fn update_status(&self, truck_id: i32, status: &str) -> Result<(), AppError>;
+    async fn update_location(
+        &self,
+        truck_id: i32,
+        node_id: i32,
+        flagged: bool,
+    ) -> Result<(), AppError>;
     async fn find_tow_truck_by_id(&self, id: i32) -> Result<Option<TowTruck>, AppError>;
+    async fn find_tow_trucks_by_driver_id(&self, driver_id: i32)
+        -> Result<Vec<TowTruck>, AppError>;
+    async fn get_location_history(
+        &self,
+        truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i32,
+    ) -> Result<Vec<Location>, AppError>;
+    async fn get_latest_locations(
+        &self,
+        truck_ids: &[i32],
+    ) -> Result<Vec<LatestLocation>, AppError>;
+    async fn insert_locations(&self, locations: &[NewLocation]) -> Result<(), AppError>;
 }
 
 #[derive(Debug)]
@@ -33,6 +99,8 @@ pub struct TowTruckService<
     tow_truck_repository: T,
     order_repository: U,
     map_repository: V,
+    location_check: LocationCheckConfig,
+    dispatch: DispatchConfig,
 }
 
 impl<
@@ -41,19 +109,29 @@ impl<
         V: MapRepository + std::fmt::Debug,
     > TowTruckService<T, U, V>
 {
-    pub fn new(tow_truck_repository: T, order_repository: U, map_repository: V) -> Self {
+    pub fn new(
+        tow_truck_repository: T,
+        order_repository: U,
+        map_repository: V,
+        location_check: LocationCheckConfig,
+        dispatch: DispatchConfig,
+    ) -> Self {
         TowTruckService {
             tow_truck_repository,
             order_repository,
             map_repository,
+            location_check,
+            dispatch,
         }
     }
 
+    #[instrument(skip(self))]
     pub async fn get_tow_truck_by_id(&self, id: i32) -> Result<Option<TowTruckDto>, AppError> {
         let tow_truck = self.tow_truck_repository.find_tow_truck_by_id(id).await?;
-        Ok(tow_truck.map(TowTruckDto::from_entity))
+        Ok(tow_truck.map(|tow_truck| self.to_dto(tow_truck)))
     }
 
+    #[instrument(skip(self))]
     pub async fn get_all_tow_trucks(
         &self,
         page: i32,
@@ -67,123 +145,615 @@ impl<
             .await?;
         let tow_truck_dtos = tow_trucks
             .into_iter()
-            .map(TowTruckDto::from_entity)
+            .map(|tow_truck| self.to_dto(tow_truck))
             .collect();
 
         Ok(tow_truck_dtos)
     }
 
+    #[instrument(skip(self))]
     pub async fn update_location(&self, truck_id: i32, node_id: i32) -> Result<(), AppError> {
+        let latest = self
+            .tow_truck_repository
+            .get_latest_locations(&[truck_id])
+            .await?
+            .pop()
+            .ok_or(AppError::Coded(ErrorCode::TowTruckNotFound))?;
+
+        let action = self.location_check.action;
+        let violation = match action {
+            PlausibilityAction::Off => None,
+            _ => self.check_location(&latest, node_id).await?,
+        };
+
+        let flagged = match violation {
+            None => false,
+            Some(code) => {
+                let reason = match code {
+                    ErrorCode::NodeOutsideArea => "outside_area",
+                    _ => "unreachable",
+                };
+                let rejected = action == PlausibilityAction::Reject;
+                let label = if rejected { "reject" } else { "flag" };
+                warn!(truck_id, node_id, reason, label, "不正な位置情報");
+                metrics().inc_implausible_location(reason, label);
+                if rejected {
+                    return Err(AppError::Coded(code));
+                }
+                true
+            }
+        };
+
         self.tow_truck_repository
-            .update_location(truck_id, node_id)
+            .update_location(truck_id, node_id, flagged)
             .await?;
+        publish_location(latest.area_id, truck_id, node_id, Utc::now());
 
         Ok(())
     }
 
+    #[instrument(skip(self))]
     pub async fn get_nearest_available_tow_trucks(
         &self,
         order_id: i32,
     ) -> Result<Option<TowTruckDto>, AppError> {
-        let order = self.order_repository.find_order_by_id(order_id).await?;
+        self.get_next_available_tow_truck(order_id, &[]).await
+    }
+
+    // 申し出を辞退した、または期限切れにしたレッカー車を除いて、配車戦略で次に選ばれるもの
+    #[instrument(skip(self))]
+    pub async fn get_next_available_tow_truck(
+        &self,
+        order_id: i32,
+        excluded_tow_truck_ids: &[i32],
+    ) -> Result<Option<TowTruckDto>, AppError> {
+        let _timer = metrics().nearest_tow_truck_search_timer();
+        let order = self
+            .order_repository
+            .find_order_by_id(order_id)
+            .await
+            .map_err(|e| e.not_found_as(ErrorCode::OrderNotFound))?;
         let area_id = self
             .map_repository
             .get_area_id_by_node_id(order.node_id)
             .await?;
-        let tow_trucks = self
+        let mut tow_trucks = self
             .tow_truck_repository
             .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
             .await?;
+        // 位置の報告が途絶えたレッカー車は最後の位置にいるとは限らない
+        let stale_before = self.stale_before();
+        tow_trucks.retain(|truck| {
+            !is_stale(truck, stale_before) && !excluded_tow_truck_ids.contains(&truck.id)
+        });
 
-        let nodes = self.map_repository.get_all_nodes(Some(area_id)).await?;
-        let edges = self.map_repository.get_all_edges(Some(area_id)).await?;
+        let strategy = self.dispatch.strategy_for(area_id);
+        let candidates = self.load_candidates(tow_trucks, strategy).await?;
+        let pending_orders = if strategy.uses_pending_orders() {
+            self.order_repository.find_pending_orders(area_id).await?
+        } else {
+            Vec::new()
+        };
 
-        let mut graph = Graph::new();
-        for node in nodes {
-            graph.add_node(node);
-        }
-        for edge in edges {
-            graph.add_edge(edge);
-        }
+        let graph = self.build_graph(area_id).await?;
+        let oracle = GraphDistanceOracle::new(&graph);
+        let selected = strategy.select(&order, &pending_orders, &candidates, &oracle);
+        debug!(
+            strategy = strategy.name(),
+            area_id,
+            tow_truck_id = ?selected.map(|index| candidates[index].tow_truck.id),
+            "配車候補を選びました"
+        );
+
+        Ok(selected
+            .and_then(|index| candidates.into_iter().nth(index))
+            .map(|candidate| self.to_dto(candidate.tow_truck)))
+    }
 
-        /*
-        let sorted_tow_trucks_by_distance = {
-            let mut tow_trucks_with_distance: Vec<_> = tow_trucks
+    // エリアの配車待ちの依頼すべてに空きレッカー車を割り当てる計画を作る。DB は変更しない
+    #[instrument(skip(self))]
+    pub async fn create_assignment_plan(
+        &self,
+        area_id: i32,
+    ) -> Result<AssignmentPlanDto, AppError> {
+        let orders = self.order_repository.find_pending_orders(area_id).await?;
+        let mut tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, Some("available".to_string()), Some(area_id))
+            .await?;
+        let stale_before = self.stale_before();
+        tow_trucks.retain(|truck| !is_stale(truck, stale_before));
+
+        let order_node_ids: Vec<i32> = orders.iter().map(|order| order.node_id).collect();
+        let truck_node_ids: Vec<i32> = tow_trucks.iter().map(|truck| truck.node_id).collect();
+        let costs: Vec<Vec<Option<i64>>> = if orders.is_empty() || tow_trucks.is_empty() {
+            vec![Vec::new(); orders.len()]
+        } else {
+            self.build_graph(area_id)
+                .await?
+                .distance_matrix(&order_node_ids, &truck_node_ids)
                 .into_iter()
-                .map(|truck| {
-                    let distance = calculate_distance(&graph, truck.node_id, order.node_id);
-                    (distance, truck)
+                .map(|row| {
+                    row.into_iter()
+                        .map(|distance| {
+                            distance
+                                .filter(|&distance| distance <= MAX_DISPATCH_DISTANCE)
+                                .map(i64::from)
+                        })
+                        .collect()
                 })
-                .collect();
-            println!("{:?}", tow_trucks_with_distance);
+                .collect()
+        };
+
+        let mut assignments = Vec::new();
+        let mut unassigned_order_ids = Vec::new();
+        let mut assigned_trucks = HashSet::new();
+        for (i, column) in min_cost_assignment(&costs).into_iter().enumerate() {
+            match column {
+                Some(j) => {
+                    assigned_trucks.insert(j);
+                    assignments.push(PlannedAssignmentDto {
+                        order_id: orders[i].id,
+                        tow_truck_id: tow_trucks[j].id,
+                        distance: costs[i][j].unwrap_or_default() as i32,
+                    });
+                }
+                None => unassigned_order_ids.push(orders[i].id),
+            }
+        }
+
+        Ok(AssignmentPlanDto {
+            area_id,
+            total_distance: assignments.iter().map(|a| i64::from(a.distance)).sum(),
+            assignments,
+            unassigned_order_ids,
+            unassigned_tow_truck_ids: tow_trucks
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| !assigned_trucks.contains(j))
+                .map(|(_, truck)| truck.id)
+                .collect(),
+        })
+    }
+}
 
-            tow_trucks_with_distance.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
-            tow_trucks_with_distance
+impl<
+        T: TowTruckRepository + std::fmt::Debug,
+        U: OrderRepository + std::fmt::Debug,
+        V: MapRepository + std::fmt::Debug,
+    > TowTruckService<T, U, V>
+{
+    // 新しい位置がエリア外、または前回の位置から経過時間内に到達できない場合にそのエラーコードを返す
+    async fn check_location(
+        &self,
+        latest: &LatestLocation,
+        node_id: i32,
+    ) -> Result<Option<ErrorCode>, AppError> {
+        let area_id = self
+            .map_repository
+            .get_area_id_by_node_id(node_id)
+            .await
+            .map_err(|e| AppError::from(e).not_found_as(ErrorCode::NodeNotFound))?;
+
+        if area_id != latest.area_id {
+            return Ok(Some(ErrorCode::NodeOutsideArea));
+        }
+
+        let (last_node_id, last_seen) = match (latest.node_id, latest.timestamp) {
+            (Some(last_node_id), Some(last_seen)) if last_node_id != node_id => {
+                (last_node_id, last_seen)
+            }
+            _ => return Ok(None),
         };
-        */
 
+        let graph = self.build_graph(area_id).await?;
+        if self.within_reach(&graph, last_node_id, node_id, Utc::now() - last_seen) {
+            Ok(None)
+        } else {
+            Ok(Some(ErrorCode::ImplausibleLocation))
+        }
+    }
 
-        
-        let sorted_tow_trucks_by_distance = {
+    // 経過時間内に道路上を移動できる距離に to があるか
+    fn within_reach(
+        &self,
+        graph: &Graph,
+        from_node_id: i32,
+        to_node_id: i32,
+        elapsed: chrono::Duration,
+    ) -> bool {
+        if from_node_id == to_node_id {
+            return true;
+        }
+        // 時計のずれで経過時間が負や0になっても、最低1秒分の移動は許す
+        let elapsed_secs = (elapsed.num_milliseconds() as f64 / 1000.0).max(1.0);
+        let max_distance = self.location_check.max_speed * elapsed_secs;
 
-            let truck_node_ids: Vec<i32> = tow_trucks.iter().map(|truck| truck.node_id).collect();
-            let distances = graph.find_nearest_point(order.node_id, &truck_node_ids);
+        matches!(
+            graph.shortest_route(from_node_id, to_node_id),
+            Some((distance, _)) if distance as f64 <= max_distance
+        )
+    }
 
-            let mut tow_trucks_with_distance: Vec<_> = distances
-                .into_iter()
-                .map(|(distance, node_id)| {
-                    let truck = tow_trucks.iter().find(|truck| truck.node_id == node_id).unwrap().clone();
-                    (distance, truck)
-                })
-                .collect();
+    #[instrument(skip(self))]
+    pub async fn get_stale_tow_trucks(
+        &self,
+        area: Option<i32>,
+    ) -> Result<Vec<TowTruckDto>, AppError> {
+        let stale_before = self.stale_before();
+        let tow_trucks = self
+            .tow_truck_repository
+            .get_paginated_tow_trucks(0, -1, None, area)
+            .await?;
 
+        Ok(tow_trucks
+            .into_iter()
+            .filter(|truck| is_stale(truck, stale_before))
+            .map(|truck| TowTruckDto::from_entity(truck, true))
+            .collect())
+    }
 
-            /*
-            let mut tow_trucks_with_distance: Vec<_> = tow_trucks
-                .into_iter()
-                .map(|truck| {
-                    //println!("distance_duration0 开始计时");
-                    //let distance_start = Instant::now();
-                    let distance = calculate_distance(&mut graph, truck.node_id, order.node_id);
-                    //let distance_duration0 = distance_start.elapsed();
-                    //println!("distance_duration0 时间间隔: {:?}", distance_duration0);
-                    (distance, truck)
+    // 戦略が稼働状況を参照しない場合は集計を省く
+    async fn load_candidates(
+        &self,
+        tow_trucks: Vec<TowTruck>,
+        strategy: &dyn DispatchStrategy,
+    ) -> Result<Vec<Candidate>, AppError> {
+        let mut workloads = HashMap::new();
+        if strategy.uses_workload() {
+            let tow_truck_ids: Vec<i32> = tow_trucks.iter().map(|truck| truck.id).collect();
+            let since = Utc::now() - chrono::Duration::seconds(self.dispatch.workload_window_secs);
+            for workload in self
+                .order_repository
+                .get_tow_truck_workloads(&tow_truck_ids, since)
+                .await?
+            {
+                workloads.insert(workload.tow_truck_id, workload);
+            }
+        }
+
+        Ok(tow_trucks
+            .into_iter()
+            .map(|tow_truck| {
+                let workload = workloads.remove(&tow_truck.id);
+                Candidate {
+                    last_active_time: workload.as_ref().and_then(|w| w.last_active_time),
+                    recent_orders: workload.map(|w| w.recent_orders).unwrap_or(0),
+                    tow_truck,
+                }
+            })
+            .collect())
+    }
+
+    // 判定しない設定の場合は None
+    fn stale_before(&self) -> Option<DateTime<Utc>> {
+        let secs = self.location_check.stale_after_secs;
+        (secs > 0).then(|| Utc::now() - chrono::Duration::seconds(secs))
+    }
+
+    fn to_dto(&self, tow_truck: TowTruck) -> TowTruckDto {
+        let stale = is_stale(&tow_truck, self.stale_before());
+        TowTruckDto::from_entity(tow_truck, stale)
+    }
+
+    async fn build_graph(&self, area_id: i32) -> Result<Arc<Graph>, AppError> {
+        load_area_graph(&self.map_repository, area_id).await
+    }
+
+    // locations.timestamp は秒精度なので、重複判定と保存の前に秒未満を切り捨てる
+    #[instrument(skip(self, records), fields(count = records.len()))]
+    pub async fn update_locations_batch(
+        &self,
+        records: Vec<NewLocation>,
+    ) -> Result<BatchUpdateLocationResponseDto, AppError> {
+        let mut truck_ids: Vec<i32> = records.iter().map(|r| r.tow_truck_id).collect();
+        truck_ids.sort_unstable();
+        truck_ids.dedup();
+        let mut node_ids: Vec<i32> = records.iter().map(|r| r.node_id).collect();
+        node_ids.sort_unstable();
+        node_ids.dedup();
+
+        let latest: HashMap<i32, LatestLocation> = self
+            .tow_truck_repository
+            .get_latest_locations(&truck_ids)
+            .await?
+            .into_iter()
+            .map(|location| (location.tow_truck_id, location))
+            .collect();
+        let node_areas: HashMap<i32, i32> = self
+            .map_repository
+            .get_area_ids_by_node_ids(&node_ids)
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut candidates = Vec::new();
+        let mut skipped = Vec::new();
+        let mut seen = HashSet::new();
+        let now = Utc::now();
+
+        for (index, mut record) in records.into_iter().enumerate() {
+            record.timestamp = record
+                .timestamp
+                .with_nanosecond(0)
+                .unwrap_or(record.timestamp);
+
+            let reason = skip_reason(
+                &record,
+                latest.get(&record.tow_truck_id),
+                node_areas.get(&record.node_id).copied(),
+                &mut seen,
+                now,
+            );
+
+            match reason {
+                Some(reason) => skipped.push(SkippedLocationDto {
+                    index,
+                    tow_truck_id: record.tow_truck_id,
+                    reason,
+                }),
+                None => candidates.push((index, record)),
+            }
+        }
+
+        // バッチ内の順序が前後していても、履歴は時刻順に積む
+        candidates.sort_by_key(|(_, record)| (record.timestamp, record.tow_truck_id));
+
+        // 単体の位置更新と同じく、直前の位置から経過時間内に到達できるかを確かめる。
+        // 直前の位置は、記録済みの最新位置か、このバッチで先に受け付けたサンプル
+        let action = self.location_check.action;
+        let mut previous: HashMap<i32, (i32, DateTime<Utc>)> = latest
+            .values()
+            .filter_map(|truck| Some((truck.tow_truck_id, (truck.node_id?, truck.timestamp?))))
+            .collect();
+        let mut accepted = Vec::new();
+
+        for (index, mut record) in candidates {
+            let truck_id = record.tow_truck_id;
+            let last = match action {
+                PlausibilityAction::Off => None,
+                _ => previous.get(&truck_id).copied(),
+            };
+            if let Some((last_node_id, last_seen)) = last {
+                let area_id = latest[&truck_id].area_id;
+                let graph = self.build_graph(area_id).await?;
+                let elapsed = record.timestamp - last_seen;
+                if !self.within_reach(&graph, last_node_id, record.node_id, elapsed) {
+                    let rejected = action == PlausibilityAction::Reject;
+                    let label = if rejected { "reject" } else { "flag" };
+                    warn!(truck_id, node_id = record.node_id, label, "不正な位置情報");
+                    metrics().inc_implausible_location("unreachable", label);
+                    if rejected {
+                        skipped.push(SkippedLocationDto {
+                            index,
+                            tow_truck_id: truck_id,
+                            reason: SkipReason::Implausible,
+                        });
+                        continue;
+                    }
+                    record.flagged = true;
+                }
+            }
+            previous.insert(truck_id, (record.node_id, record.timestamp));
+            accepted.push(record);
+        }
+        skipped.sort_by_key(|skipped| skipped.index);
+
+        self.tow_truck_repository
+            .insert_locations(&accepted)
+            .await?;
+
+        // 時刻順に並んでいるので、レッカー車ごとに最後のサンプルが最新位置になる
+        let newest: HashMap<i32, &NewLocation> = accepted
+            .iter()
+            .map(|record| (record.tow_truck_id, record))
+            .collect();
+        for (truck_id, record) in newest {
+            if let Some(truck) = latest.get(&truck_id) {
+                publish_location(truck.area_id, truck_id, record.node_id, record.timestamp);
+            }
+        }
+
+        Ok(BatchUpdateLocationResponseDto {
+            accepted: accepted.len(),
+            skipped,
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub async fn get_location_history(
+        &self,
+        truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        expand_route: bool,
+    ) -> Result<LocationHistoryDto, AppError> {
+        let tow_truck = self
+            .tow_truck_repository
+            .find_tow_truck_by_id(truck_id)
+            .await?
+            .ok_or(AppError::Coded(ErrorCode::TowTruckNotFound))?;
+        // 1件多く取得して、上限で打ち切ったかどうかを判定する
+        let mut locations = self
+            .tow_truck_repository
+            .get_location_history(truck_id, from, to, MAX_LOCATION_HISTORY + 1)
+            .await?;
+        let truncated = locations.len() > MAX_LOCATION_HISTORY as usize;
+        locations.truncate(MAX_LOCATION_HISTORY as usize);
+
+        // 同じノードに留まっている間のサンプルは経路展開では区間にしない
+        let route = if expand_route {
+            let graph = self.build_graph(tow_truck.area_id).await?;
+            let route = locations
+                .windows(2)
+                .filter(|pair| pair[0].node_id != pair[1].node_id)
+                .map(|pair| {
+                    let (from_node_id, to_node_id) = (pair[0].node_id, pair[1].node_id);
+                    match graph.shortest_route(from_node_id, to_node_id) {
+                        Some((distance, node_ids)) => RouteSegmentDto {
+                            from_node_id,
+                            to_node_id,
+                            distance: Some(distance),
+                            node_ids,
+                        },
+                        // エリア外への移動などで到達できない区間は端点だけを返す
+                        None => RouteSegmentDto {
+                            from_node_id,
+                            to_node_id,
+                            distance: None,
+                            node_ids: vec![from_node_id, to_node_id],
+                        },
+                    }
                 })
                 .collect();
-            //println!("{:?}", tow_trucks_with_distance);
-            */
-
-            if let Some(min_truck) = tow_trucks_with_distance.iter().min_by(|a, b| a.0.partial_cmp(&b.0).unwrap()).cloned() {
-                // 移除最小元素并将其放在前面
-                tow_trucks_with_distance.retain(|x| !(x.0 == min_truck.0 && x.1.node_id == min_truck.1.node_id));
-                let mut sorted_trucks = vec![min_truck];
-                sorted_trucks.extend(tow_trucks_with_distance);
-                sorted_trucks
-            } else {
-                // 如果没有元素，直接返回空向量
-                vec![]
-            }
+            Some(route)
+        } else {
+            None
         };
-        
-        //println!("sorted_tow_trucks_by_distance[0] : {}",sorted_tow_trucks_by_distance[0]);
-        //println!("sorted_tow_trucks_by_distance[0].0 : {}",sorted_tow_trucks_by_distance[0].0);
-        //println!("sorted_tow_trucks_by_distance[1].0 : {}",sorted_tow_trucks_by_distance[0].0);
-
-        if sorted_tow_trucks_by_distance.is_empty() || sorted_tow_trucks_by_distance[0].0 > 10000000
-        {
-            return Ok(None);
-        }
 
-        let sorted_tow_truck_dtos: Vec<TowTruckDto> = sorted_tow_trucks_by_distance
+        let samples = locations
             .into_iter()
-            .map(|(_, truck)| TowTruckDto::from_entity(truck))
+            .map(|location| LocationSampleDto {
+                id: location.id,
+                node_id: location.node_id,
+                timestamp: location.timestamp,
+                flagged: location.flagged,
+            })
             .collect();
 
-        //println!("sorted_tow_truck_dtos.first() : {}",sorted_tow_truck_dtos.first());
-        Ok(sorted_tow_truck_dtos.first().cloned())
+        Ok(LocationHistoryDto {
+            tow_truck_id: truck_id,
+            samples,
+            truncated,
+            route,
+        })
+    }
+}
+
+fn is_stale(tow_truck: &TowTruck, stale_before: Option<DateTime<Utc>>) -> bool {
+    stale_before.is_some_and(|stale_before| tow_truck.last_seen < stale_before)
+}
+
+// バッチ登録する位置情報を受け付けない理由。受け付ける場合は None
+fn skip_reason(
+    record: &NewLocation,
+    truck: Option<&LatestLocation>,
+    node_area_id: Option<i32>,
+    seen: &mut HashSet<(i32, DateTime<Utc>)>,
+    now: DateTime<Utc>,
+) -> Option<SkipReason> {
+    let truck = match truck {
+        Some(truck) => truck,
+        None => return Some(SkipReason::UnknownTowTruck),
+    };
+    // 未来の時刻を受け付けると、それ以降の正しいサンプルがすべて Stale になる
+    if record.timestamp > now + chrono::Duration::seconds(MAX_CLOCK_SKEW_SECS) {
+        return Some(SkipReason::FutureTimestamp);
+    }
+    if node_area_id != Some(truck.area_id) {
+        return Some(SkipReason::NodeOutsideArea);
+    }
+    // 記録済みの最新位置以前のサンプルは遅れて届いたものとして捨てる
+    if truck.timestamp.is_some_and(|t| record.timestamp <= t) {
+        return Some(SkipReason::Stale);
+    }
+    if !seen.insert((record.tow_truck_id, record.timestamp)) {
+        return Some(SkipReason::Duplicate);
     }
+    None
 }
 
-fn calculate_distance(graph: &mut Graph, node_id_1: i32, node_id_2: i32) -> i32 {
-    graph.shortest_path(node_id_1, node_id_2)
+fn publish_location(area_id: i32, tow_truck_id: i32, node_id: i32, timestamp: DateTime<Utc>) {
+    tow_truck_event_hub().publish(
+        area_id,
+        "location",
+        &TowTruckEventDto {
+            tow_truck_id,
+            area_id,
+            node_id: Some(node_id),
+            status: None,
+            timestamp,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 7, 25, 12, 0, 0).unwrap()
+    }
+
+    fn truck(timestamp: Option<DateTime<Utc>>) -> LatestLocation {
+        LatestLocation {
+            tow_truck_id: 1,
+            area_id: 1,
+            node_id: timestamp.map(|_| 10),
+            timestamp,
+        }
+    }
+
+    fn record(timestamp: DateTime<Utc>) -> NewLocation {
+        NewLocation {
+            tow_truck_id: 1,
+            node_id: 11,
+            timestamp,
+            flagged: false,
+        }
+    }
+
+    fn reason(record: &NewLocation, truck: &LatestLocation) -> Option<SkipReason> {
+        skip_reason(record, Some(truck), Some(1), &mut HashSet::new(), now())
+    }
+
+    #[test]
+    fn future_timestamps_are_skipped() {
+        let latest = truck(Some(now() - chrono::Duration::minutes(1)));
+        let skew = chrono::Duration::seconds(MAX_CLOCK_SKEW_SECS);
+
+        assert_eq!(reason(&record(now()), &latest), None);
+        assert_eq!(reason(&record(now() + skew), &latest), None);
+        assert_eq!(
+            reason(
+                &record(now() + skew + chrono::Duration::seconds(1)),
+                &latest
+            ),
+            Some(SkipReason::FutureTimestamp)
+        );
+        // 位置が未登録のレッカー車でも同じ
+        assert_eq!(
+            reason(&record(now() + chrono::Duration::days(1)), &truck(None)),
+            Some(SkipReason::FutureTimestamp)
+        );
+    }
+
+    #[test]
+    fn other_skip_reasons() {
+        let truck = truck(Some(now() - chrono::Duration::minutes(1)));
+        let mut seen = HashSet::new();
+        let sample = record(now());
+
+        assert_eq!(
+            skip_reason(&sample, None, Some(1), &mut seen, now()),
+            Some(SkipReason::UnknownTowTruck)
+        );
+        assert_eq!(
+            skip_reason(&sample, Some(&truck), Some(2), &mut seen, now()),
+            Some(SkipReason::NodeOutsideArea)
+        );
+        assert_eq!(
+            reason(&record(now() - chrono::Duration::minutes(1)), &truck),
+            Some(SkipReason::Stale)
+        );
+        assert_eq!(
+            skip_reason(&sample, Some(&truck), Some(1), &mut seen, now()),
+            None
+        );
+        assert_eq!(
+            skip_reason(&sample, Some(&truck), Some(1), &mut seen, now()),
+            Some(SkipReason::Duplicate)
+        );
+    }
 }
@@ -1,6 +1,149 @@
-use actix_web::{HttpResponse, ResponseError};
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use serde::Serialize;
+use sqlx::mysql::MySqlDatabaseError;
 use thiserror::Error;
+use tracing::error;
+
+use crate::middlewares::request_id_middleware::current_request_id;
+
+// MySQL のエラー番号
+const ER_DUP_ENTRY: u16 = 1062;
+const ER_ROW_IS_REFERENCED: u16 = 1451;
+const ER_NO_REFERENCED_ROW: u16 = 1452;
+
+// クライアントが分岐に使う安定したエラーコード。値を変える場合は互換性に注意する
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    BadRequest,
+    ValidationFailed,
+    InvalidReference,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    OrderNotFound,
+    UserNotFound,
+    DispatcherNotFound,
+    TowTruckNotFound,
+    NodeNotFound,
+    Conflict,
+    DuplicateEntry,
+    TowTruckUnavailable,
+    PlanOutdated,
+    OrderNotCancellable,
+    OrderNotDispatched,
+    OfferNotPending,
+    InvalidJobTransition,
+    NodeOutsideArea,
+    ImplausibleLocation,
+    InternalServerError,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "BAD_REQUEST",
+            ErrorCode::ValidationFailed => "VALIDATION_FAILED",
+            ErrorCode::InvalidReference => "INVALID_REFERENCE",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::OrderNotFound => "ORDER_NOT_FOUND",
+            ErrorCode::UserNotFound => "USER_NOT_FOUND",
+            ErrorCode::DispatcherNotFound => "DISPATCHER_NOT_FOUND",
+            ErrorCode::TowTruckNotFound => "TOW_TRUCK_NOT_FOUND",
+            ErrorCode::NodeNotFound => "NODE_NOT_FOUND",
+            ErrorCode::Conflict => "CONFLICT",
+            ErrorCode::DuplicateEntry => "DUPLICATE_ENTRY",
+            ErrorCode::TowTruckUnavailable => "TOW_TRUCK_UNAVAILABLE",
+            ErrorCode::PlanOutdated => "PLAN_OUTDATED",
+            ErrorCode::OrderNotCancellable => "ORDER_NOT_CANCELLABLE",
+            ErrorCode::OrderNotDispatched => "ORDER_NOT_DISPATCHED",
+            ErrorCode::OfferNotPending => "OFFER_NOT_PENDING",
+            ErrorCode::InvalidJobTransition => "INVALID_JOB_TRANSITION",
+            ErrorCode::NodeOutsideArea => "NODE_OUTSIDE_AREA",
+            ErrorCode::ImplausibleLocation => "IMPLAUSIBLE_LOCATION",
+            ErrorCode::InternalServerError => "INTERNAL_SERVER_ERROR",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::ValidationFailed | ErrorCode::InvalidReference => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound
+            | ErrorCode::OrderNotFound
+            | ErrorCode::UserNotFound
+            | ErrorCode::DispatcherNotFound
+            | ErrorCode::TowTruckNotFound
+            | ErrorCode::NodeNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict
+            | ErrorCode::DuplicateEntry
+            | ErrorCode::TowTruckUnavailable
+            | ErrorCode::PlanOutdated
+            | ErrorCode::OrderNotCancellable
+            | ErrorCode::OrderNotDispatched
+            | ErrorCode::OfferNotPending
+            | ErrorCode::InvalidJobTransition => StatusCode::CONFLICT,
+            ErrorCode::NodeOutsideArea | ErrorCode::ImplausibleLocation => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ErrorCode::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad Request",
+            ErrorCode::ValidationFailed => "Validation Failed",
+            ErrorCode::InvalidReference => "Referenced resource does not exist",
+            ErrorCode::Unauthorized => "Unauthorized",
+            ErrorCode::Forbidden => "Forbidden",
+            ErrorCode::NotFound => "Not Found",
+            ErrorCode::OrderNotFound => "Order Not Found",
+            ErrorCode::UserNotFound => "User Not Found",
+            ErrorCode::DispatcherNotFound => "Dispatcher Not Found",
+            ErrorCode::TowTruckNotFound => "Tow Truck Not Found",
+            ErrorCode::NodeNotFound => "Node Not Found",
+            ErrorCode::Conflict => "Conflict",
+            ErrorCode::DuplicateEntry => "Duplicate Entry",
+            ErrorCode::TowTruckUnavailable => "Tow Truck Unavailable",
+            ErrorCode::PlanOutdated => "Orders or tow trucks in the plan have changed",
+            ErrorCode::OrderNotCancellable => "Only pending or dispatched orders can be cancelled",
+            ErrorCode::OrderNotDispatched => "Order has no assigned tow truck",
+            ErrorCode::OfferNotPending => "Order has no offer awaiting a response",
+            ErrorCode::InvalidJobTransition => {
+                "Job must be accepted and advanced in order: en_route, on_site, completed"
+            }
+            ErrorCode::NodeOutsideArea => "Node is outside the tow truck's area",
+            ErrorCode::ImplausibleLocation => "Location is not reachable in the elapsed time",
+            ErrorCode::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum AppError {
@@ -14,31 +157,88 @@ pub enum AppError {
     Conflict,
     #[error("Internal Server Error")]
     InternalServerError,
+    #[error("{}", .0.message())]
+    Coded(ErrorCode),
+    #[error("Validation Failed")]
+    Validation(Vec<FieldError>),
     #[error(transparent)]
     SqlxError(#[from] sqlx::Error),
 }
 
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::BadRequest => ErrorCode::BadRequest,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::NotFound => ErrorCode::NotFound,
+            AppError::Conflict => ErrorCode::Conflict,
+            AppError::InternalServerError => ErrorCode::InternalServerError,
+            AppError::Coded(code) => *code,
+            AppError::Validation(_) => ErrorCode::ValidationFailed,
+            AppError::SqlxError(err) => sqlx_error_code(err),
+        }
+    }
+
+    // 行が見つからなかった場合だけ、リソース固有のコードに置き換える
+    pub fn not_found_as(self, code: ErrorCode) -> Self {
+        match self.code() {
+            ErrorCode::NotFound => AppError::Coded(code),
+            _ => self,
+        }
+    }
+}
+
+fn sqlx_error_code(err: &sqlx::Error) -> ErrorCode {
+    match err {
+        sqlx::Error::RowNotFound => ErrorCode::NotFound,
+        sqlx::Error::Database(db_err) => match db_err
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .map(|e| e.number())
+        {
+            Some(ER_DUP_ENTRY) => ErrorCode::DuplicateEntry,
+            Some(ER_NO_REFERENCED_ROW) | Some(ER_ROW_IS_REFERENCED) => ErrorCode::InvalidReference,
+            _ => ErrorCode::InternalServerError,
+        },
+        _ => ErrorCode::InternalServerError,
+    }
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
+    code: ErrorCode,
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    details: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
 }
 
 impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        self.code().status_code()
+    }
+
     fn error_response(&self) -> HttpResponse {
-        let error_message = self.to_string();
-        let error_response = ErrorResponse {
-            message: error_message,
+        let code = self.code();
+        if code == ErrorCode::InternalServerError {
+            error!(error = ?self, "internal server error");
+        }
+
+        // DBエラーの詳細はクライアントに返さず、コードに対応する文言だけを返す
+        let message = match self {
+            AppError::SqlxError(_) => code.message().to_string(),
+            _ => self.to_string(),
+        };
+        let details = match self {
+            AppError::Validation(errors) => errors.clone(),
+            _ => Vec::new(),
         };
 
-        match *self {
-            AppError::BadRequest => HttpResponse::BadRequest().json(error_response),
-            AppError::Unauthorized => HttpResponse::Unauthorized().json(error_response),
-            AppError::NotFound => HttpResponse::NotFound().json(error_response),
-            AppError::Conflict => HttpResponse::Conflict().json(error_response),
-            AppError::InternalServerError => {
-                HttpResponse::InternalServerError().json(error_response)
-            }
-            AppError::SqlxError(_) => HttpResponse::InternalServerError().json(error_response),
-        }
+        HttpResponse::build(code.status_code()).json(ErrorResponse {
+            code,
+            message,
+            details,
+            request_id: current_request_id(),
+        })
     }
 }
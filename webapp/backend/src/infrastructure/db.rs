@@ -1,9 +1,103 @@
-use sqlx::mysql::MySqlPool;
+use serde::Serialize;
+use sqlx::mysql::{MySqlPool, MySqlPoolOptions};
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::warn;
 
-pub async fn create_pool() -> MySqlPool {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    MySqlPool::connect(&database_url)
-        .await
-        .expect("Failed to create pool")
+#[derive(Debug, Clone)]
+pub struct DbConfig {
+    pub database_url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub max_lifetime: Option<Duration>,
+    pub connect_retries: u32,
+    pub retry_backoff: Duration,
+}
+
+impl DbConfig {
+    pub fn from_env() -> Self {
+        DbConfig {
+            database_url: env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            max_connections: env_or("DB_MAX_CONNECTIONS", 20),
+            min_connections: env_or("DB_MIN_CONNECTIONS", 2),
+            acquire_timeout: Duration::from_secs(env_or("DB_ACQUIRE_TIMEOUT_SECS", 30)),
+            // 0 を指定するとタイムアウトを無効にする
+            idle_timeout: seconds_or_none(env_or("DB_IDLE_TIMEOUT_SECS", 600)),
+            max_lifetime: seconds_or_none(env_or("DB_MAX_LIFETIME_SECS", 1800)),
+            connect_retries: env_or("DB_CONNECT_RETRIES", 10),
+            retry_backoff: Duration::from_millis(env_or("DB_CONNECT_BACKOFF_MS", 500)),
+        }
+    }
+}
+
+pub(crate) fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn seconds_or_none(secs: u64) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+pub async fn create_pool(config: &DbConfig) -> Result<MySqlPool, sqlx::Error> {
+    let mut backoff = config.retry_backoff;
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+
+        let result = MySqlPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .connect_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .max_lifetime(config.max_lifetime)
+            .connect(&config.database_url)
+            .await;
+
+        match result {
+            Ok(pool) => return Ok(pool),
+            Err(err) if attempt <= config.connect_retries => {
+                warn!(
+                    "DB接続に失敗しました ({}/{}): {:?}。{:?}後に再試行します",
+                    attempt, config.connect_retries, err, backoff
+                );
+                actix_web::rt::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(30));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub is_closed: bool,
+}
+
+pub fn pool_stats(pool: &MySqlPool, config: &DbConfig) -> PoolStats {
+    let size = pool.size();
+    let idle = pool.num_idle();
+
+    PoolStats {
+        size,
+        idle,
+        in_use: (size as usize).saturating_sub(idle),
+        max_connections: config.max_connections,
+        min_connections: config.min_connections,
+        is_closed: pool.is_closed(),
+    }
 }
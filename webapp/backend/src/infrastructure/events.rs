@@ -0,0 +1,237 @@
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::broadcast;
+
+// 再接続時の Last-Event-ID から再送できるよう、トピックごとに直近のイベントを残しておく
+const REPLAY_BUFFER_SIZE: usize = 256;
+const CHANNEL_CAPACITY: usize = 256;
+
+#[derive(Debug)]
+pub struct Event {
+    pub id: String,
+    pub name: &'static str,
+    pub data: String,
+    seq: u64,
+}
+
+impl Event {
+    // text/event-stream 形式の1イベント
+    pub fn to_sse(&self) -> String {
+        format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            self.id, self.name, self.data
+        )
+    }
+
+    // WebSocket で送る JSON。data はシリアライズ済みの JSON をそのまま埋め込む
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"id":"{}","event":"{}","data":{}}}"#,
+            self.id, self.name, self.data
+        )
+    }
+}
+
+struct TopicChannel {
+    sender: broadcast::Sender<Arc<Event>>,
+    recent: VecDeque<Arc<Event>>,
+}
+
+pub enum Replay {
+    // Last-Event-ID より後のイベント (空の場合もある)
+    Events(Vec<Arc<Event>>),
+    // バッファから取りこぼしがあるか、再起動前の ID が指定された。クライアントは一覧を取り直す
+    Reset,
+}
+
+pub struct EventHub {
+    // イベント ID は "{起動時刻}-{連番}"。再起動をまたいだ Last-Event-ID を見分けるため
+    boot_id: i64,
+    next_seq: AtomicU64,
+    // トピックはエリア ID や利用者 ID など、購読の単位ごとの番号
+    topics: Mutex<HashMap<i32, TopicChannel>>,
+}
+
+impl EventHub {
+    fn new() -> Self {
+        EventHub {
+            boot_id: chrono::Utc::now().timestamp_millis(),
+            next_seq: AtomicU64::new(1),
+            topics: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn publish(&self, topic: i32, name: &'static str, data: &impl Serialize) {
+        let data = match serde_json::to_string(data) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+
+        // 採番と送信を同じロックの中で行い、subscribe と順序が入れ替わらないようにする
+        let mut topics = self.topics.lock().unwrap();
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let event = Arc::new(Event {
+            id: format!("{}-{}", self.boot_id, seq),
+            name,
+            data,
+            seq,
+        });
+
+        let channel = topics.entry(topic).or_insert_with(new_channel);
+        if channel.recent.len() == REPLAY_BUFFER_SIZE {
+            channel.recent.pop_front();
+        }
+        channel.recent.push_back(event.clone());
+        // 購読者がいない場合の送信エラーは無視する
+        let _ = channel.sender.send(event);
+    }
+
+    pub fn subscribe(
+        &self,
+        topic: i32,
+        last_event_id: Option<&str>,
+    ) -> (Replay, broadcast::Receiver<Arc<Event>>) {
+        let mut topics = self.topics.lock().unwrap();
+        let channel = topics.entry(topic).or_insert_with(new_channel);
+        let receiver = channel.sender.subscribe();
+
+        let replay = match last_event_id {
+            None => Replay::Events(Vec::new()),
+            Some(id) => match self.parse_seq(id) {
+                Some(last_seq) => {
+                    let oldest = channel.recent.front().map(|e| e.seq);
+                    match oldest {
+                        // バッファの先頭より前の ID は取りこぼしがありうる
+                        Some(oldest) if last_seq + 1 < oldest => Replay::Reset,
+                        _ => Replay::Events(
+                            channel
+                                .recent
+                                .iter()
+                                .filter(|e| e.seq > last_seq)
+                                .cloned()
+                                .collect(),
+                        ),
+                    }
+                }
+                None => Replay::Reset,
+            },
+        };
+
+        (replay, receiver)
+    }
+
+    // このプロセスが採番した ID の場合だけ連番を返す
+    fn parse_seq(&self, id: &str) -> Option<u64> {
+        let (boot_id, seq) = id.split_once('-')?;
+        if boot_id.parse::<i64>().ok()? != self.boot_id {
+            return None;
+        }
+        let seq = seq.parse::<u64>().ok()?;
+        (seq < self.next_seq.load(Ordering::Relaxed)).then_some(seq)
+    }
+}
+
+fn new_channel() -> TopicChannel {
+    TopicChannel {
+        sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        recent: VecDeque::with_capacity(REPLAY_BUFFER_SIZE),
+    }
+}
+
+// エリアごとのレッカー車の位置・状態と、SLA を超えた依頼
+pub fn tow_truck_event_hub() -> &'static EventHub {
+    static EVENT_HUB: OnceLock<EventHub> = OnceLock::new();
+    EVENT_HUB.get_or_init(EventHub::new)
+}
+
+// 利用者 (users.id) ごとの依頼の状態と、ドライバーへの割り当ての申し出
+pub fn order_event_hub() -> &'static EventHub {
+    static EVENT_HUB: OnceLock<EventHub> = OnceLock::new();
+    EVENT_HUB.get_or_init(EventHub::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reset の場合は None
+    fn replayed(hub: &EventHub, topic: i32, last_event_id: Option<&str>) -> Option<Vec<String>> {
+        match hub.subscribe(topic, last_event_id).0 {
+            Replay::Events(events) => Some(events.iter().map(|e| e.data.clone()).collect()),
+            Replay::Reset => None,
+        }
+    }
+
+    fn id_of(hub: &EventHub, seq: u64) -> String {
+        format!("{}-{}", hub.boot_id, seq)
+    }
+
+    #[test]
+    fn replays_events_after_last_event_id_in_the_same_topic() {
+        let hub = EventHub::new();
+        hub.publish(1, "location", &1);
+        hub.publish(2, "location", &2);
+        hub.publish(1, "status", &3);
+        hub.publish(1, "location", &4);
+
+        assert_eq!(replayed(&hub, 1, None), Some(vec![]));
+        assert_eq!(
+            replayed(&hub, 1, Some(&id_of(&hub, 1))),
+            Some(vec!["3".to_string(), "4".to_string()])
+        );
+        // 他のトピックの ID でも、連番より後のこのトピックのイベントだけを返す
+        assert_eq!(
+            replayed(&hub, 1, Some(&id_of(&hub, 2))),
+            Some(vec!["3".to_string(), "4".to_string()])
+        );
+        assert_eq!(replayed(&hub, 1, Some(&id_of(&hub, 4))), Some(vec![]));
+        assert_eq!(replayed(&hub, 3, Some(&id_of(&hub, 4))), Some(vec![]));
+    }
+
+    #[test]
+    fn unknown_ids_reset() {
+        let hub = EventHub::new();
+        hub.publish(1, "location", &1);
+
+        // 再起動前の ID、未採番の ID、形式の違う ID
+        let other_boot = format!("{}-1", hub.boot_id - 1);
+        assert_eq!(replayed(&hub, 1, Some(&other_boot)), None);
+        assert_eq!(replayed(&hub, 1, Some(&id_of(&hub, 2))), None);
+        assert_eq!(replayed(&hub, 1, Some("abc")), None);
+        assert_eq!(replayed(&hub, 1, Some("")), None);
+    }
+
+    #[test]
+    fn resets_when_buffer_dropped_events() {
+        let hub = EventHub::new();
+        let total = REPLAY_BUFFER_SIZE as u64 + 2;
+        for value in 1..=total {
+            hub.publish(1, "location", &value);
+        }
+
+        // 連番 1, 2 はバッファから消えている
+        assert_eq!(replayed(&hub, 1, Some(&id_of(&hub, 1))), None);
+        let events = replayed(&hub, 1, Some(&id_of(&hub, 2))).unwrap();
+        assert_eq!(events.len(), REPLAY_BUFFER_SIZE);
+        assert_eq!(events.first().map(String::as_str), Some("3"));
+        assert_eq!(events.last(), Some(&total.to_string()));
+    }
+
+    #[test]
+    fn subscribers_receive_events_published_after_subscribe() {
+        let hub = EventHub::new();
+        hub.publish(1, "location", &1);
+        let (_, mut receiver) = hub.subscribe(1, None);
+        hub.publish(2, "location", &2);
+        hub.publish(1, "status", &"busy");
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(
+            event.to_sse(),
+            format!("id: {}\nevent: status\ndata: \"busy\"\n\n", id_of(&hub, 3))
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
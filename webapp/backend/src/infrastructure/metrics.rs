@@ -0,0 +1,227 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sqlx::MySqlPool;
+use std::sync::OnceLock;
+
+use super::db::{pool_stats, DbConfig};
+
+pub struct Metrics {
+    registry: Registry,
+    http_requests_total: IntCounterVec,
+    http_request_duration_seconds: HistogramVec,
+    db_query_duration_seconds: HistogramVec,
+    db_pool_connections: IntGaugeVec,
+    dispatch_total: IntCounterVec,
+    nearest_tow_truck_search_duration_seconds: HistogramVec,
+    implausible_locations_total: IntCounterVec,
+    auto_dispatch_total: IntCounterVec,
+    order_escalations_total: IntCounterVec,
+    dispatch_offers_total: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        let db_query_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "db_query_duration_seconds",
+                "Repository query latency in seconds",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["repository", "method"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "MySQL pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let dispatch_total = IntCounterVec::new(
+            Opts::new("dispatch_total", "Number of dispatched orders"),
+            &["area_id"],
+        )
+        .unwrap();
+        let nearest_tow_truck_search_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "nearest_tow_truck_search_duration_seconds",
+                "Nearest available tow truck search latency in seconds",
+            ),
+            &[],
+        )
+        .unwrap();
+
+        let implausible_locations_total = IntCounterVec::new(
+            Opts::new(
+                "implausible_locations_total",
+                "Number of implausible location updates",
+            ),
+            &["reason", "action"],
+        )
+        .unwrap();
+        let auto_dispatch_total = IntCounterVec::new(
+            Opts::new(
+                "auto_dispatch_total",
+                "Number of automatic dispatch attempts by result",
+            ),
+            &["area_id", "result"],
+        )
+        .unwrap();
+        let order_escalations_total = IntCounterVec::new(
+            Opts::new(
+                "order_escalations_total",
+                "Number of pending orders escalated for exceeding the SLA",
+            ),
+            &["area_id"],
+        )
+        .unwrap();
+        let dispatch_offers_total = IntCounterVec::new(
+            Opts::new(
+                "dispatch_offers_total",
+                "Number of dispatch offers answered by drivers or expired, by result",
+            ),
+            &["result"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_query_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(dispatch_total.clone())).unwrap();
+        registry
+            .register(Box::new(nearest_tow_truck_search_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(implausible_locations_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(auto_dispatch_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(order_escalations_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(dispatch_offers_total.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            db_query_duration_seconds,
+            db_pool_connections,
+            dispatch_total,
+            nearest_tow_truck_search_duration_seconds,
+            implausible_locations_total,
+            auto_dispatch_total,
+            order_escalations_total,
+            dispatch_offers_total,
+        }
+    }
+
+    pub fn observe_http_request(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        self.http_requests_total
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.http_request_duration_seconds
+            .with_label_values(&[method, route])
+            .observe(seconds);
+    }
+
+    pub fn db_timer(&self, repository: &str, method: &str) -> HistogramTimer {
+        self.db_query_duration_seconds
+            .with_label_values(&[repository, method])
+            .start_timer()
+    }
+
+    pub fn inc_dispatch(&self, area_id: i32) {
+        self.dispatch_total
+            .with_label_values(&[&area_id.to_string()])
+            .inc();
+    }
+
+    pub fn nearest_tow_truck_search_timer(&self) -> HistogramTimer {
+        self.nearest_tow_truck_search_duration_seconds
+            .with_label_values(&[])
+            .start_timer()
+    }
+
+    pub fn inc_implausible_location(&self, reason: &str, action: &str) {
+        self.implausible_locations_total
+            .with_label_values(&[reason, action])
+            .inc();
+    }
+
+    pub fn inc_auto_dispatch(&self, area_id: i32, result: &str) {
+        self.auto_dispatch_total
+            .with_label_values(&[&area_id.to_string(), result])
+            .inc();
+    }
+
+    pub fn inc_order_escalation(&self, area_id: i32) {
+        self.order_escalations_total
+            .with_label_values(&[&area_id.to_string()])
+            .inc();
+    }
+
+    pub fn inc_dispatch_offer(&self, result: &str) {
+        self.dispatch_offers_total
+            .with_label_values(&[result])
+            .inc();
+    }
+
+    pub fn render(&self, pool: &MySqlPool, config: &DbConfig) -> String {
+        let stats = pool_stats(pool, config);
+        for (state, value) in [
+            ("size", stats.size as i64),
+            ("idle", stats.idle as i64),
+            ("in_use", stats.in_use as i64),
+            ("max", stats.max_connections as i64),
+        ] {
+            self.db_pool_connections
+                .with_label_values(&[state])
+                .set(value);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+pub fn db_timer(repository: &str, method: &str) -> HistogramTimer {
+    metrics().db_timer(repository, method)
+}
@@ -1 +1,4 @@
 pub mod db;
+pub mod events;
+pub mod metrics;
+pub mod telemetry;
@@ -0,0 +1,21 @@
+use std::env;
+use tracing_subscriber::EnvFilter;
+
+// LOG_LEVEL (未設定なら RUST_LOG) でログレベルを、LOG_FORMAT=json で JSON 出力を切り替える
+pub fn init_tracing() {
+    let filter = env::var("LOG_LEVEL")
+        .or_else(|_| env::var("RUST_LOG"))
+        .map(EnvFilter::new)
+        .unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => builder
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+        _ => builder.init(),
+    }
+}
@@ -2,12 +2,24 @@ use std::sync::Arc;
 
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
-use api::{auth_handler, health_check_handler, map_handler, order_handler, tow_truck_handler};
+use api::{
+    auth_handler, driver_handler, health_check_handler, map_handler, metrics_handler,
+    order_handler, stream_handler, tow_truck_handler,
+};
 use domains::map_service::MapService;
 use domains::{
-    auth_service::AuthService, order_service::OrderService, tow_truck_service::TowTruckService,
+    auth_service::AuthService,
+    auto_dispatch::{run_auto_dispatch, AutoDispatchControl},
+    dispatch_offer::{run_offer_expiry, OfferConfig},
+    dispatch_strategy::DispatchConfig,
+    order_service::{EtaConfig, OrderService, PriorityConfig},
+    sla_escalation::{run_sla_escalation, SlaConfig},
+    tow_truck_service::{LocationCheckConfig, TowTruckService},
 };
 use middlewares::auth_middleware::AuthMiddleware;
+use middlewares::metrics_middleware::MetricsMiddleware;
+use middlewares::request_id_middleware::{RequestIdMiddleware, REQUEST_ID_HEADER};
+use middlewares::tracing_middleware::TracingMiddleware;
 use repositories::auth_repository::AuthRepositoryImpl;
 use repositories::map_repository::MapRepositoryImpl;
 use repositories::order_repository::OrderRepositoryImpl;
@@ -24,7 +36,12 @@ mod utils;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let pool = infrastructure::db::create_pool().await;
+    infrastructure::telemetry::init_tracing();
+
+    let db_config = infrastructure::db::DbConfig::from_env();
+    let pool = infrastructure::db::create_pool(&db_config)
+        .await
+        .map_err(std::io::Error::other)?;
     let mut port = 8080;
 
     if cfg!(debug_assertions) {
@@ -38,14 +55,39 @@ async fn main() -> std::io::Result<()> {
         TowTruckRepositoryImpl::new(pool.clone()),
         OrderRepositoryImpl::new(pool.clone()),
         MapRepositoryImpl::new(pool.clone()),
+        LocationCheckConfig::from_env(),
+        DispatchConfig::from_env(),
     ));
+    let offer_config = OfferConfig::from_env();
     let order_service = web::Data::new(OrderService::new(
         OrderRepositoryImpl::new(pool.clone()),
         TowTruckRepositoryImpl::new(pool.clone()),
         AuthRepositoryImpl::new(pool.clone()),
         MapRepositoryImpl::new(pool.clone()),
+        EtaConfig::from_env(),
+        PriorityConfig::from_env(),
+        offer_config.clone(),
     ));
     let map_service = web::Data::new(MapService::new(MapRepositoryImpl::new(pool.clone())));
+    let auto_dispatch_control = web::Data::new(AutoDispatchControl::from_env());
+    actix_web::rt::spawn(run_auto_dispatch(
+        auto_dispatch_control.clone().into_inner(),
+        tow_truck_service.clone().into_inner(),
+        order_service.clone().into_inner(),
+    ));
+    if offer_config.is_enabled() {
+        actix_web::rt::spawn(run_offer_expiry(
+            offer_config,
+            tow_truck_service.clone().into_inner(),
+            order_service.clone().into_inner(),
+        ));
+    }
+    actix_web::rt::spawn(run_sla_escalation(
+        SlaConfig::from_env(),
+        order_service.clone().into_inner(),
+    ));
+    let db_pool = web::Data::new(pool.clone());
+    let db_config = web::Data::new(db_config);
 
     HttpServer::new(move || {
         let mut cors = Cors::default();
@@ -57,6 +99,9 @@ async fn main() -> std::io::Result<()> {
                 actix_web::http::header::ACCEPT,
             ])
             .allowed_header(actix_web::http::header::CONTENT_TYPE)
+            .allowed_header(REQUEST_ID_HEADER)
+            .allowed_header("last-event-id")
+            .expose_headers(vec![REQUEST_ID_HEADER])
             .supports_credentials()
             .max_age(3600);
 
@@ -65,13 +110,34 @@ async fn main() -> std::io::Result<()> {
             .app_data(auth_service.clone())
             .app_data(order_service.clone())
             .app_data(map_service.clone())
+            .app_data(auto_dispatch_control.clone())
+            .app_data(db_pool.clone())
+            .app_data(db_config.clone())
             .wrap(cors)
+            .wrap(MetricsMiddleware)
+            .wrap(TracingMiddleware)
+            .wrap(RequestIdMiddleware)
+            .service(
+                web::resource("/metrics").route(web::get().to(metrics_handler::metrics_handler)),
+            )
             .service(
                 web::scope("/api")
                     .service(
                         web::resource("/health_check")
                             .route(web::get().to(health_check_handler::health_check_handler)),
                     )
+                    .service(
+                        web::resource("/health/live")
+                            .route(web::get().to(health_check_handler::liveness_handler)),
+                    )
+                    .service(
+                        web::resource("/health/ready")
+                            .route(web::get().to(health_check_handler::readiness_handler)),
+                    )
+                    .service(
+                        web::resource("/health/pool")
+                            .route(web::get().to(health_check_handler::pool_stats_handler)),
+                    )
                     .service(
                         web::resource("/validate_session")
                             .route(web::get().to(auth_handler::validate_session_handler)),
@@ -102,6 +168,16 @@ async fn main() -> std::io::Result<()> {
                                     web::post().to(tow_truck_handler::update_location_handler),
                                 ),
                             )
+                            .service(web::resource("/location/batch").route(
+                                web::post().to(tow_truck_handler::batch_update_location_handler),
+                            ))
+                            .service(
+                                web::resource("/events")
+                                    .route(web::get().to(stream_handler::tow_truck_events_handler)),
+                            )
+                            .service(web::resource("/stale").route(
+                                web::get().to(tow_truck_handler::get_stale_tow_trucks_handler),
+                            ))
                             .service(web::resource("/nearest").route(
                                 web::get().to(
                                     tow_truck_handler::get_nearest_available_tow_trucks_handler,
@@ -110,7 +186,10 @@ async fn main() -> std::io::Result<()> {
                             .service(
                                 web::resource("/{id}")
                                     .route(web::get().to(tow_truck_handler::get_tow_truck_handler)),
-                            ),
+                            )
+                            .service(web::resource("/{id}/history").route(
+                                web::get().to(tow_truck_handler::get_location_history_handler),
+                            )),
                     )
                     .service(
                         web::scope("/order")
@@ -133,9 +212,75 @@ async fn main() -> std::io::Result<()> {
                             .service(web::resource("/dispatcher").route(
                                 web::post().to(order_handler::create_dispatcher_order_handler),
                             ))
+                            .service(
+                                web::resource("/assignment_plan").route(
+                                    web::get().to(order_handler::get_assignment_plan_handler),
+                                ),
+                            )
+                            .service(web::resource("/assignment_plan/accept").route(
+                                web::post().to(order_handler::accept_assignment_plan_handler),
+                            ))
+                            .service(
+                                web::resource("/auto_dispatch")
+                                    .route(web::get().to(order_handler::get_auto_dispatch_handler))
+                                    .route(
+                                        web::put().to(order_handler::update_auto_dispatch_handler),
+                                    ),
+                            )
+                            .service(
+                                web::resource("/auto_dispatch/kill_switch").route(
+                                    web::put().to(order_handler::update_kill_switch_handler),
+                                ),
+                            )
+                            .service(
+                                web::resource("/auto_dispatch/hold")
+                                    .route(web::put().to(order_handler::hold_order_handler)),
+                            )
+                            .service(
+                                web::resource("/events")
+                                    .route(web::get().to(stream_handler::order_events_ws_handler)),
+                            )
                             .service(
                                 web::resource("/{id}")
                                     .route(web::get().to(order_handler::get_order_handler)),
+                            )
+                            .service(
+                                web::resource("/{id}/cancel")
+                                    .route(web::post().to(order_handler::cancel_order_handler)),
+                            )
+                            .service(
+                                web::resource("/{id}/reassign")
+                                    .route(web::post().to(order_handler::reassign_order_handler)),
+                            )
+                            .service(
+                                web::resource("/{id}/unassign")
+                                    .route(web::post().to(order_handler::unassign_order_handler)),
+                            )
+                            .service(
+                                web::resource("/{id}/assignments").route(
+                                    web::get().to(order_handler::get_order_assignments_handler),
+                                ),
+                            )
+                            .service(
+                                web::resource("/{id}/offer/accept")
+                                    .route(web::post().to(order_handler::accept_offer_handler)),
+                            )
+                            .service(
+                                web::resource("/{id}/offer/decline")
+                                    .route(web::post().to(order_handler::decline_offer_handler)),
+                            ),
+                    )
+                    .service(
+                        web::scope("/driver")
+                            .wrap(AuthMiddleware::new(auth_service_for_middleware.clone()))
+                            .service(
+                                web::resource("/jobs")
+                                    .route(web::get().to(driver_handler::get_jobs_handler)),
+                            )
+                            .service(
+                                web::resource("/jobs/{order_id}/status").route(
+                                    web::put().to(driver_handler::update_job_status_handler),
+                                ),
                             ),
                     )
                     .service(
@@ -144,6 +289,10 @@ async fn main() -> std::io::Result<()> {
                             .service(
                                 web::resource("/update_edge")
                                     .route(web::put().to(map_handler::update_edge_handler)),
+                            )
+                            .service(
+                                web::resource("/nearest_node")
+                                    .route(web::get().to(map_handler::nearest_node_handler)),
                             ),
                     ),
             )
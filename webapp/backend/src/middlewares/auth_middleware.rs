@@ -1,15 +1,38 @@
+use std::rc::Rc;
 use std::sync::Arc;
 
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
-    Error,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, FromRequest, HttpMessage, HttpRequest,
 };
 use futures_util::future::{ready, LocalBoxFuture, Ready};
 
 use crate::{
-    domains::auth_service::AuthService, repositories::auth_repository::AuthRepositoryImpl,
+    domains::auth_service::AuthService, errors::AppError,
+    repositories::auth_repository::AuthRepositoryImpl,
 };
 
+// 認証済みリクエストのユーザー。AuthMiddleware がリクエストの extensions に入れる
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub user_id: i32,
+    pub role: String,
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<AuthenticatedUser>()
+                .cloned()
+                .ok_or(AppError::Unauthorized),
+        )
+    }
+}
+
 pub struct AuthMiddleware {
     auth_service: Arc<AuthService<AuthRepositoryImpl>>,
 }
@@ -34,14 +57,14 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareMiddleware {
-            service,
+            service: Rc::new(service),
             auth_service: self.auth_service.clone(),
         }))
     }
 }
 
 pub struct AuthMiddlewareMiddleware<S> {
-    service: S,
+    service: Rc<S>,
     auth_service: Arc<AuthService<AuthRepositoryImpl>>,
 }
 
@@ -62,23 +85,26 @@ where
             .get("Authorization")
             .and_then(|h| h.to_str().ok())
             .map(|s| s.to_string());
-        let auth_header = Arc::new(auth_header);
 
         let auth_service = self.auth_service.clone();
-        let fut = self.service.call(req);
+        let service = self.service.clone();
 
         Box::pin(async move {
-            let is_valid_token = match &*auth_header {
-                Some(token) => auth_service.validate_session(token).await.is_ok(),
-                None => false,
+            // 無効化済み・存在しないセッションはどちらも 401
+            let user = match &auth_header {
+                Some(token) => auth_service.authenticate(token).await?,
+                None => None,
             };
 
-            if is_valid_token {
-                fut.await
-            } else {
-                Err(actix_web::error::ErrorUnauthorized(
-                    "Invalid or missing token",
-                ))
+            match user {
+                Some(user) => {
+                    req.extensions_mut().insert(AuthenticatedUser {
+                        user_id: user.id,
+                        role: user.role,
+                    });
+                    service.call(req).await
+                }
+                None => Err(AppError::Unauthorized.into()),
             }
         })
     }
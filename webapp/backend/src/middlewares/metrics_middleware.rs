@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error,
+};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+
+use crate::infrastructure::metrics::metrics;
+
+pub struct MetricsMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for MetricsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddlewareMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(MetricsMiddlewareMiddleware { service }))
+    }
+}
+
+pub struct MetricsMiddlewareMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for MetricsMiddlewareMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let elapsed = start.elapsed().as_secs_f64();
+
+            // ルートのパターン (/api/order/{id} など) をラベルにして、IDごとに系列が増えないようにする
+            let (route, status) = match &res {
+                Ok(res) => (
+                    res.request().match_pattern(),
+                    res.response().status().as_u16(),
+                ),
+                Err(err) => (None, err.as_response_error().status_code().as_u16()),
+            };
+            let route = route.unwrap_or_else(|| "unmatched".to_string());
+            metrics().observe_http_request(&method, &route, status, elapsed);
+
+            res
+        })
+    }
+}
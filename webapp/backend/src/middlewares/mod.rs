@@ -1 +1,4 @@
 pub mod auth_middleware;
+pub mod metrics_middleware;
+pub mod request_id_middleware;
+pub mod tracing_middleware;
@@ -0,0 +1,94 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, HttpMessage,
+};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: String;
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+// リクエストの処理中であれば、そのリクエストIDを返す
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+// クライアントから渡されたIDはログに載るので、長さと文字種を制限する
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+pub struct RequestIdMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestIdMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddlewareMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddlewareMiddleware { service }))
+    }
+}
+
+pub struct RequestIdMiddlewareMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddlewareMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+        let http_req = req.request().clone();
+
+        let fut = CURRENT_REQUEST_ID.sync_scope(request_id.clone(), || self.service.call(req));
+
+        Box::pin(CURRENT_REQUEST_ID.scope(request_id.clone(), async move {
+            // 内側のエラー(認証の401など)もここでレスポンスにして、リクエストIDを付ける
+            let mut res = match fut.await {
+                Ok(res) => res.map_into_left_body(),
+                Err(err) => ServiceResponse::from_err(err, http_req).map_into_right_body(),
+            };
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        }))
+    }
+}
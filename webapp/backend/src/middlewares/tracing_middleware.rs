@@ -0,0 +1,86 @@
+use std::time::Instant;
+
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use tracing::{field, info, info_span, Instrument};
+
+use super::request_id_middleware::RequestId;
+
+pub struct TracingMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for TracingMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracingMiddlewareMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TracingMiddlewareMiddleware { service }))
+    }
+}
+
+pub struct TracingMiddlewareMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for TracingMiddlewareMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        // RequestIdMiddleware の内側で動くので、IDは既に割り当てられている
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+        let span = info_span!(
+            "http_request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+            status = field::Empty,
+        );
+
+        // ハンドラ・サービス・リポジトリのスパンはすべてこのスパンの子になる
+        let fut = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+
+        Box::pin(
+            async move {
+                let res = fut.await;
+                let status = match &res {
+                    Ok(res) => res.response().status().as_u16(),
+                    Err(err) => err.as_response_error().status_code().as_u16(),
+                };
+                tracing::Span::current().record("status", status);
+                info!(
+                    elapsed_ms = start.elapsed().as_millis() as u64,
+                    "request completed"
+                );
+
+                res
+            }
+            .instrument(span),
+        )
+    }
+}
@@ -0,0 +1,252 @@
+// 到達できない組み合わせのコスト。実際の距離の合計より十分大きくし、割り当て件数の最大化を優先させる
+const UNREACHABLE_COST: i64 = 1 << 40;
+
+// 行を列に重複なく割り当て、コストの合計が最小になる組み合わせを返す (ハンガリアン法)
+// costs[i][j] が None の組や、行の長さが足りない組は割り当てない。戻り値は行ごとに割り当てた列
+pub fn min_cost_assignment(costs: &[Vec<Option<i64>>]) -> Vec<Option<usize>> {
+    let rows = costs.len();
+    let columns = costs.iter().map(|row| row.len()).max().unwrap_or(0);
+    if rows == 0 || columns == 0 {
+        return vec![None; rows];
+    }
+
+    let entry = |i: usize, j: usize| costs[i].get(j).copied().flatten();
+    // 途中の計算が桁あふれしないよう、コストの絶対値は到達できない組のコストまでに抑える
+    let cost = |i: usize, j: usize| {
+        entry(i, j).map_or(UNREACHABLE_COST, |c| {
+            c.clamp(-UNREACHABLE_COST, UNREACHABLE_COST)
+        })
+    };
+    // 行数が列数以下になる向きで解く
+    let assigned: Vec<Option<usize>> = if rows <= columns {
+        hungarian(rows, columns, cost)
+            .into_iter()
+            .map(Some)
+            .collect()
+    } else {
+        let mut assigned = vec![None; rows];
+        for (j, i) in hungarian(columns, rows, |j, i| cost(i, j))
+            .into_iter()
+            .enumerate()
+        {
+            assigned[i] = Some(j);
+        }
+        assigned
+    };
+
+    assigned
+        .into_iter()
+        .enumerate()
+        .map(|(i, j)| j.filter(|&j| entry(i, j).is_some()))
+        .collect()
+}
+
+// n <= m の n 行 m 列のコストについて、各行に割り当てた列を返す。O(n^2 m)
+fn hungarian(n: usize, m: usize, cost: impl Fn(usize, usize) -> i64) -> Vec<usize> {
+    // 1 始まりで扱い、0 番目の列を番兵に使う
+    let mut u = vec![0i64; n + 1];
+    let mut v = vec![0i64; m + 1];
+    // 列ごとに割り当てた行
+    let mut row_of = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+
+    for i in 1..=n {
+        row_of[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![i64::MAX; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = i64::MAX;
+            let mut j1 = 0;
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let reduced = cost(i0 - 1, j - 1) - u[i0] - v[j];
+                if reduced < min_v[j] {
+                    min_v[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=m {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+
+        // 増加路をたどって割り当てを入れ替える
+        loop {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assigned = vec![0; n];
+    for j in 1..=m {
+        if row_of[j] != 0 {
+            assigned[row_of[j] - 1] = j - 1;
+        }
+    }
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 割り当て件数が最大の中でコストの合計が最小のもの。(件数, 合計)
+    fn brute_force(costs: &[Vec<Option<i64>>]) -> (usize, i64) {
+        fn search(
+            costs: &[Vec<Option<i64>>],
+            i: usize,
+            used: &mut Vec<bool>,
+            count: usize,
+            total: i64,
+            best: &mut (usize, i64),
+        ) {
+            if i == costs.len() {
+                if count > best.0 || (count == best.0 && total < best.1) {
+                    *best = (count, total);
+                }
+                return;
+            }
+            search(costs, i + 1, used, count, total, best);
+            for j in 0..used.len() {
+                if let Some(cost) = costs[i][j] {
+                    if !used[j] {
+                        used[j] = true;
+                        search(costs, i + 1, used, count + 1, total + cost, best);
+                        used[j] = false;
+                    }
+                }
+            }
+        }
+
+        let columns = costs.first().map_or(0, |row| row.len());
+        let mut best = (0, 0);
+        search(costs, 0, &mut vec![false; columns], 0, 0, &mut best);
+        best
+    }
+
+    // 列の重複がないことを確かめて (件数, 合計) を返す
+    fn summarize(costs: &[Vec<Option<i64>>], assigned: &[Option<usize>]) -> (usize, i64) {
+        assert_eq!(assigned.len(), costs.len());
+        let mut columns: Vec<usize> = assigned.iter().flatten().copied().collect();
+        let count = columns.len();
+        columns.sort_unstable();
+        columns.dedup();
+        assert_eq!(columns.len(), count, "{:?}", assigned);
+
+        let total = assigned
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| costs[i][(*j)?])
+            .sum();
+        (count, total)
+    }
+
+    fn matrix(rows: &[&[i64]]) -> Vec<Vec<Option<i64>>> {
+        // 負の値は到達できない組を表す
+        rows.iter()
+            .map(|row| row.iter().map(|&c| (c >= 0).then_some(c)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn square() {
+        let costs = matrix(&[&[4, 1, 3], &[2, 0, 5], &[3, 2, 2]]);
+        let assigned = min_cost_assignment(&costs);
+        assert_eq!(assigned, vec![Some(1), Some(0), Some(2)]);
+        assert_eq!(summarize(&costs, &assigned), (3, 5));
+    }
+
+    #[test]
+    fn more_columns_than_rows() {
+        let costs = matrix(&[&[7, 3, 9, 1], &[8, 2, 6, 1]]);
+        let assigned = min_cost_assignment(&costs);
+        assert_eq!(assigned, vec![Some(3), Some(1)]);
+    }
+
+    #[test]
+    fn more_rows_than_columns() {
+        let costs = matrix(&[&[7, 8], &[3, 2], &[9, 6], &[1, 1]]);
+        let assigned = min_cost_assignment(&costs);
+        assert_eq!(summarize(&costs, &assigned), (2, 3));
+        assert_eq!(assigned.iter().flatten().count(), 2);
+    }
+
+    #[test]
+    fn unreachable_pairs_are_never_assigned() {
+        // 安い組を取るより、到達できる組を増やす方を優先する
+        let costs = matrix(&[&[1, 100], &[2, -1]]);
+        assert_eq!(min_cost_assignment(&costs), vec![Some(1), Some(0)]);
+
+        let costs = matrix(&[&[-1, -1], &[-1, 5]]);
+        assert_eq!(min_cost_assignment(&costs), vec![None, Some(1)]);
+
+        let costs = matrix(&[&[-1, -1, -1], &[-1, -1, -1]]);
+        assert_eq!(min_cost_assignment(&costs), vec![None, None]);
+    }
+
+    #[test]
+    fn degenerate_inputs() {
+        assert!(min_cost_assignment(&[]).is_empty());
+        assert_eq!(min_cost_assignment(&[vec![], vec![]]), vec![None, None]);
+        // 長さの違う行は足りない列を到達できない組として扱う
+        let costs = vec![vec![Some(5)], vec![Some(1), Some(1)]];
+        assert_eq!(min_cost_assignment(&costs), vec![Some(0), Some(1)]);
+        // 極端な値でも桁あふれしない
+        let costs = vec![vec![Some(i64::MAX), Some(i64::MIN)], vec![Some(0), None]];
+        assert_eq!(min_cost_assignment(&costs), vec![Some(1), Some(0)]);
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut seed: u64 = 7;
+        let mut next = |range: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % range
+        };
+
+        for _ in 0..300 {
+            let rows = next(5) as usize + 1;
+            let columns = next(5) as usize + 1;
+            let costs: Vec<Vec<Option<i64>>> = (0..rows)
+                .map(|_| {
+                    (0..columns)
+                        .map(|_| (next(4) != 0).then(|| next(50) as i64))
+                        .collect()
+                })
+                .collect();
+
+            let assigned = min_cost_assignment(&costs);
+            assert_eq!(
+                summarize(&costs, &assigned),
+                brute_force(&costs),
+                "{:?}",
+                costs
+            );
+        }
+    }
+}
@@ -5,6 +5,7 @@ use std::collections::VecDeque;
 #[derive(FromRow, Clone, Debug)]
 pub struct Node {
     pub id: i32,
+    pub area_id: i32,
     pub x: i32,
     pub y: i32,
 }
@@ -20,7 +21,6 @@ pub struct Edge {
 pub struct Graph {
     pub nodes: HashMap<i32, Node>,
     pub edges: HashMap<i32, Vec<Edge>>,
-    pub distances_cache: HashMap<i32, HashMap<i32, i32>>,
 }
 
 impl Graph {
@@ -28,7 +28,6 @@ impl Graph {
         Graph {
             nodes: HashMap::new(),
             edges: HashMap::new(),
-            distances_cache: HashMap::new(),
         }
     }
 
@@ -53,17 +52,10 @@ impl Graph {
             .push(reverse_edge);
     }
 
-    pub fn shortest_path(&mut self, from_node_id: i32, to_node_id: i32) -> i32 {
-
-        // Check if we already have the shortest path from the cache
-        if let Some(distances) = self.distances_cache.get(&from_node_id) {
-            if let Some(&distance) = distances.get(&to_node_id) {
-                return distance;
-            }
-        }
-
-        // Initialize distances and in_queue
+    // from から到達できる全ノードへの最短距離と、最短経路で直前に通るノード
+    fn search(&self, from_node_id: i32) -> (HashMap<i32, i32>, HashMap<i32, i32>) {
         let mut distances = HashMap::new();
+        let mut previous = HashMap::new();
         let mut in_queue = HashMap::new();
         let mut queue = VecDeque::new();
 
@@ -84,6 +76,7 @@ impl Graph {
 
                     if new_distance < *current_distance {
                         distances.insert(edge.node_b_id, new_distance);
+                        previous.insert(edge.node_b_id, current_node_id);
 
                         if !*in_queue.get(&edge.node_b_id).unwrap_or(&false) {
                             queue.push_back(edge.node_b_id);
@@ -94,91 +87,45 @@ impl Graph {
             }
         }
 
-        // Cache the computed distances
-        self.distances_cache.insert(from_node_id, distances.clone());
-
-        // Return the distance to the target node
-        distances.get(&to_node_id).cloned().unwrap_or(i32::MAX)
-
-        /*
-
-        let mut distances = HashMap::new();
-        let mut in_queue = HashMap::new();
-        let mut queue = VecDeque::new();
-
-        distances.insert(from_node_id, 0);
-        queue.push_back(from_node_id);
-        in_queue.insert(from_node_id, true);
-
-        while let Some(current_node_id) = queue.pop_front() {
-            in_queue.insert(current_node_id, false);
-
-            if let Some(edges) = self.edges.get(&current_node_id) {
-                for edge in edges {
-                    let new_distance = distances
-                        .get(&current_node_id)
-                        .and_then(|d: &i32| d.checked_add(edge.weight))
-                        .unwrap_or(i32::MAX);
-                    let current_distance = distances.get(&edge.node_b_id).unwrap_or(&i32::MAX);
-
-                    if new_distance < *current_distance {
-                        distances.insert(edge.node_b_id, new_distance);
-
-                        if !*in_queue.get(&edge.node_b_id).unwrap_or(&false) {
-                            queue.push_back(edge.node_b_id);
-                            in_queue.insert(edge.node_b_id, true);
-                        }
-                    }
-                }
-            }
-        }
-
-        distances.get(&to_node_id).cloned().unwrap_or(i32::MAX)
-        */
-    
+        (distances, previous)
     }
 
-    pub fn find_nearest_point(&self, from_node_id: i32, target_nodes: &[i32]) -> Vec<(i32, i32)> {
-        let mut distances = HashMap::new();
-        let mut in_queue = HashMap::new();
-        let mut queue = VecDeque::new();
-
-        distances.insert(from_node_id, 0);
-        queue.push_back(from_node_id);
-        in_queue.insert(from_node_id, true);
-
-        while let Some(current_node_id) = queue.pop_front() {
-            in_queue.insert(current_node_id, false);
-
-            if let Some(edges) = self.edges.get(&current_node_id) {
-                for edge in edges {
-                    let new_distance = distances
-                        .get(&current_node_id)
-                        .and_then(|d: &i32| d.checked_add(edge.weight))
-                        .unwrap_or(i32::MAX);
-                    let current_distance = distances.get(&edge.node_b_id).unwrap_or(&i32::MAX);
-
-                    if new_distance < *current_distance {
-                        distances.insert(edge.node_b_id, new_distance);
+    // from から到達できる全ノードへの最短距離
+    pub fn distances_from(&self, from_node_id: i32) -> HashMap<i32, i32> {
+        self.search(from_node_id).0
+    }
 
-                        if !*in_queue.get(&edge.node_b_id).unwrap_or(&false) {
-                            queue.push_back(edge.node_b_id);
-                            in_queue.insert(edge.node_b_id, true);
-                        }
-                    }
-                }
-            }
-        }
+    // sources の各ノードから targets の各ノードへの最短距離。到達できない組は None
+    pub fn distance_matrix(&self, sources: &[i32], targets: &[i32]) -> Vec<Vec<Option<i32>>> {
+        // 同じノードにいる始点は探索を1回で済ませる
+        let mut distances: HashMap<i32, HashMap<i32, i32>> = HashMap::new();
+        sources
+            .iter()
+            .map(|source| {
+                let distances = distances
+                    .entry(*source)
+                    .or_insert_with(|| self.distances_from(*source));
+                targets
+                    .iter()
+                    .map(|target| distances.get(target).copied())
+                    .collect()
+            })
+            .collect()
+    }
 
-        let mut result = Vec::new();
+    // 最短経路の距離と、経由するノード列 (from と to を含む) を返す。到達できない場合は None
+    pub fn shortest_route(&self, from_node_id: i32, to_node_id: i32) -> Option<(i32, Vec<i32>)> {
+        let (distances, previous) = self.search(from_node_id);
+        let distance = *distances.get(&to_node_id)?;
 
-        for &target_node in target_nodes {
-            if let Some(&distance) = distances.get(&target_node) {
-                result.push((distance, target_node));
-            }
+        let mut path = vec![to_node_id];
+        let mut current = to_node_id;
+        while current != from_node_id {
+            current = previous[&current];
+            path.push(current);
         }
+        path.reverse();
 
-        result
+        Some((distance, path))
     }
-
 }
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+#[derive(FromRow, Clone, Debug)]
+pub struct Location {
+    pub id: i32,
+    pub node_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub flagged: bool,
+}
+
+// バッチ登録する位置情報
+#[derive(Clone, Debug)]
+pub struct NewLocation {
+    pub tow_truck_id: i32,
+    pub node_id: i32,
+    pub timestamp: DateTime<Utc>,
+    pub flagged: bool,
+}
+
+// レッカー車の所属エリアと最新位置。位置が未登録の場合 node_id と timestamp は None
+#[derive(FromRow, Clone, Debug)]
+pub struct LatestLocation {
+    pub tow_truck_id: i32,
+    pub area_id: i32,
+    pub node_id: Option<i32>,
+    pub timestamp: Option<DateTime<Utc>>,
+}
@@ -1,4 +1,7 @@
+pub mod assignment;
 pub mod graph;
+pub mod location;
 pub mod order;
+pub mod spatial_index;
 pub mod tow_truck;
 pub mod user;
@@ -12,4 +12,145 @@ pub struct Order {
     pub car_value: f64,
     pub order_time: DateTime<Utc>,
     pub completed_time: Option<DateTime<Utc>>,
+    pub escalated_at: Option<DateTime<Utc>>,
+}
+
+// レッカー車ごとの依頼の担当状況
+#[derive(FromRow, Clone, Debug)]
+pub struct TowTruckWorkload {
+    pub tow_truck_id: i32,
+    pub last_active_time: Option<DateTime<Utc>>,
+    pub recent_orders: i64,
+}
+
+// SLA の判定に使う、エスカレーションされていない配車待ちの依頼
+#[derive(FromRow, Clone, Debug)]
+pub struct UnescalatedOrder {
+    pub id: i32,
+    pub area_id: i32,
+    pub order_time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NewOrderCancellation {
+    pub cancelled_by: i32,
+    pub cancelled_by_role: String,
+    pub reason: String,
+    pub note: Option<String>,
+    pub cancelled_at: DateTime<Utc>,
+}
+
+#[derive(FromRow, Clone, Debug)]
+pub struct OrderAssignment {
+    pub id: i32,
+    pub order_id: i32,
+    pub tow_truck_id: i32,
+    pub assigned_by: Option<i32>,
+    pub assigned_at: DateTime<Utc>,
+    pub unassigned_at: Option<DateTime<Utc>>,
+    pub unassigned_by: Option<i32>,
+    pub unassign_reason: Option<String>,
+    // offered / accepted / declined / expired
+    pub offer_status: String,
+    pub responded_at: Option<DateTime<Utc>>,
+    pub en_route_at: Option<DateTime<Utc>>,
+    pub on_site_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl OrderAssignment {
+    // ドライバーから見た作業の段階
+    pub fn job_status(&self) -> &'static str {
+        if self.completed_at.is_some() {
+            "completed"
+        } else if self.on_site_at.is_some() {
+            "on_site"
+        } else if self.en_route_at.is_some() {
+            "en_route"
+        } else if self.offer_status == "accepted" {
+            "accepted"
+        } else {
+            "offered"
+        }
+    }
+
+    // 次に進められる段階。承諾前と完了後は None
+    pub fn next_job_status(&self) -> Option<&'static str> {
+        match self.job_status() {
+            "accepted" => Some("en_route"),
+            "en_route" => Some("on_site"),
+            "on_site" => Some("completed"),
+            _ => None,
+        }
+    }
+}
+
+// 割り当てを変更した配車担当者と理由。申し出の辞退・期限切れによる付け替えでは dispatcher_id は None
+#[derive(Clone, Debug)]
+pub struct AssignmentChange {
+    pub dispatcher_id: Option<i32>,
+    // 指定した場合、現在の割り当てがこのレッカー車でなければ変更しない
+    pub expected_tow_truck_id: Option<i32>,
+    pub reason: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assignment(offer_status: &str) -> OrderAssignment {
+        OrderAssignment {
+            id: 1,
+            order_id: 1,
+            tow_truck_id: 1,
+            assigned_by: None,
+            assigned_at: Utc::now(),
+            unassigned_at: None,
+            unassigned_by: None,
+            unassign_reason: None,
+            offer_status: offer_status.to_string(),
+            responded_at: None,
+            en_route_at: None,
+            on_site_at: None,
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn job_advances_en_route_then_on_site_then_completed() {
+        let mut assignment = assignment("accepted");
+        let mut steps = Vec::new();
+        while let Some(next) = assignment.next_job_status() {
+            steps.push(next);
+            let at = Some(Utc::now());
+            match next {
+                "en_route" => assignment.en_route_at = at,
+                "on_site" => assignment.on_site_at = at,
+                "completed" => assignment.completed_at = at,
+                other => panic!("unexpected status {}", other),
+            }
+            assert_eq!(assignment.job_status(), next);
+        }
+        assert_eq!(steps, vec!["en_route", "on_site", "completed"]);
+    }
+
+    #[test]
+    fn job_cannot_advance_before_acceptance() {
+        for offer_status in ["offered", "declined", "expired"] {
+            let assignment = assignment(offer_status);
+            assert_eq!(assignment.job_status(), "offered");
+            assert_eq!(assignment.next_job_status(), None);
+        }
+    }
+
+    #[test]
+    fn job_cannot_skip_a_step() {
+        let mut assignment = assignment("accepted");
+        assert_ne!(assignment.next_job_status(), Some("on_site"));
+        assert_ne!(assignment.next_job_status(), Some("completed"));
+
+        assignment.en_route_at = Some(Utc::now());
+        assert_ne!(assignment.next_job_status(), Some("completed"));
+    }
 }
@@ -0,0 +1,216 @@
+use super::graph::Node;
+
+// ノード座標の一様グリッド。1セルあたりおよそ1ノードになるようにセル幅を決める
+#[derive(Debug)]
+pub struct GridIndex {
+    min_x: i64,
+    min_y: i64,
+    cell_size: i64,
+    cols: i64,
+    rows: i64,
+    cells: Vec<Vec<Node>>,
+}
+
+impl GridIndex {
+    pub fn new(nodes: Vec<Node>) -> Self {
+        let min_x = nodes.iter().map(|n| n.x as i64).min().unwrap_or(0);
+        let max_x = nodes.iter().map(|n| n.x as i64).max().unwrap_or(0);
+        let min_y = nodes.iter().map(|n| n.y as i64).min().unwrap_or(0);
+        let max_y = nodes.iter().map(|n| n.y as i64).max().unwrap_or(0);
+
+        let width = max_x - min_x + 1;
+        let height = max_y - min_y + 1;
+        let count = nodes.len().max(1) as f64;
+        let cell_size = (((width * height) as f64 / count).sqrt().ceil() as i64).max(1);
+        let cols = width / cell_size + 1;
+        let rows = height / cell_size + 1;
+
+        let mut index = GridIndex {
+            min_x,
+            min_y,
+            cell_size,
+            cols,
+            rows,
+            cells: vec![Vec::new(); (cols * rows) as usize],
+        };
+        for node in nodes {
+            let (col, row) = index.cell_of(node.x as f64, node.y as f64);
+            let cell = (row * cols + col) as usize;
+            index.cells[cell].push(node);
+        }
+
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.iter().map(|cell| cell.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(|cell| cell.is_empty())
+    }
+
+    // 最も近いノードとその距離を返す。距離が等しい場合は id が小さい方
+    pub fn nearest(&self, x: f64, y: f64) -> Option<(&Node, f64)> {
+        let (col, row) = self.cell_of(x, y);
+        // グリッド外の点はリングを広げても空セルばかりになるので全件を見る
+        if col < 0 || row < 0 || col >= self.cols || row >= self.rows {
+            return self.nearest_linear(x, y);
+        }
+        let max_ring = col
+            .max(self.cols - 1 - col)
+            .max(row)
+            .max(self.rows - 1 - row);
+
+        let mut best: Option<(&Node, f64)> = None;
+        for ring in 0..=max_ring {
+            for (c, r) in ring_cells(col, row, ring) {
+                if c < 0 || r < 0 || c >= self.cols || r >= self.rows {
+                    continue;
+                }
+                for node in &self.cells[(r * self.cols + c) as usize] {
+                    best = closer(best, node, x, y);
+                }
+            }
+
+            // 次のリングのセルはどれも ring * cell_size より遠い
+            if let Some((_, distance)) = best {
+                if distance <= (ring * self.cell_size) as f64 {
+                    break;
+                }
+            }
+        }
+
+        best
+    }
+
+    fn nearest_linear(&self, x: f64, y: f64) -> Option<(&Node, f64)> {
+        self.cells
+            .iter()
+            .flatten()
+            .fold(None, |best, node| closer(best, node, x, y))
+    }
+
+    // グリッド外の座標は範囲外のセル番号になる
+    fn cell_of(&self, x: f64, y: f64) -> (i64, i64) {
+        let col = ((x - self.min_x as f64) / self.cell_size as f64).floor() as i64;
+        let row = ((y - self.min_y as f64) / self.cell_size as f64).floor() as i64;
+        (col, row)
+    }
+}
+
+fn closer<'a>(
+    best: Option<(&'a Node, f64)>,
+    node: &'a Node,
+    x: f64,
+    y: f64,
+) -> Option<(&'a Node, f64)> {
+    let distance = (node.x as f64 - x).hypot(node.y as f64 - y);
+    match best {
+        Some((current, d)) if d < distance || (d == distance && current.id < node.id) => best,
+        _ => Some((node, distance)),
+    }
+}
+
+// (col, row) からチェビシェフ距離がちょうど ring のセル
+fn ring_cells(col: i64, row: i64, ring: i64) -> Vec<(i64, i64)> {
+    if ring == 0 {
+        return vec![(col, row)];
+    }
+
+    let mut cells = Vec::with_capacity((ring * 8) as usize);
+    for c in (col - ring)..=(col + ring) {
+        cells.push((c, row - ring));
+        cells.push((c, row + ring));
+    }
+    for r in (row - ring + 1)..(row + ring) {
+        cells.push((col - ring, r));
+        cells.push((col + ring, r));
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(id: i32, x: i32, y: i32) -> Node {
+        Node {
+            id,
+            area_id: 1,
+            x,
+            y,
+        }
+    }
+
+    fn nearest_id(index: &GridIndex, x: f64, y: f64) -> Option<i32> {
+        index.nearest(x, y).map(|(node, _)| node.id)
+    }
+
+    #[test]
+    fn empty_index_has_no_nearest() {
+        let index = GridIndex::new(Vec::new());
+        assert!(index.is_empty());
+        assert_eq!(index.len(), 0);
+        assert!(index.nearest(0.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn nodes_on_cell_boundaries_are_found() {
+        // 4ノード・11x11 の範囲なのでセル幅は 6。x = 6 のノードは2列目のセルに入る
+        let nodes = vec![node(1, 0, 0), node(2, 6, 0), node(3, 0, 6), node(4, 10, 10)];
+        let index = GridIndex::new(nodes);
+        assert_eq!(index.len(), 4);
+
+        assert_eq!(nearest_id(&index, 6.0, 0.0), Some(2));
+        assert_eq!(nearest_id(&index, 5.9, 0.0), Some(2));
+        assert_eq!(nearest_id(&index, 2.9, 0.0), Some(1));
+        assert_eq!(nearest_id(&index, 0.0, 6.0), Some(3));
+        assert_eq!(index.nearest(10.0, 10.0).map(|(_, d)| d), Some(0.0));
+    }
+
+    #[test]
+    fn negative_coordinates() {
+        let nodes = vec![node(1, -100, -100), node(2, -5, -5), node(3, 50, -20)];
+        let index = GridIndex::new(nodes);
+
+        assert_eq!(nearest_id(&index, -99.0, -101.0), Some(1));
+        assert_eq!(nearest_id(&index, -1.0, -1.0), Some(2));
+        assert_eq!(nearest_id(&index, 40.0, -30.0), Some(3));
+        // グリッド外の点
+        assert_eq!(nearest_id(&index, -1000.0, -1000.0), Some(1));
+        assert_eq!(nearest_id(&index, 1000.0, -20.0), Some(3));
+    }
+
+    #[test]
+    fn equal_distances_prefer_smaller_id() {
+        // 登録順に関係なく id の小さい方を返す
+        let nodes = vec![node(7, 10, 0), node(3, -10, 0), node(5, 0, 10)];
+        let index = GridIndex::new(nodes);
+
+        let (nearest, distance) = index.nearest(0.0, 0.0).unwrap();
+        assert_eq!((nearest.id, distance), (3, 10.0));
+        assert_eq!(nearest_id(&index, 5.0, 5.0), Some(5));
+    }
+
+    #[test]
+    fn matches_linear_search() {
+        // 線形合同法で作った疎な点群で、全件を見た場合と同じ結果になることを確かめる
+        let mut seed: u64 = 42;
+        let mut next = |range: i32| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 33) % (range as u64 * 2 + 1)) as i32 - range
+        };
+        let nodes: Vec<Node> = (1..=200).map(|id| node(id, next(500), next(300))).collect();
+        let index = GridIndex::new(nodes);
+
+        for _ in 0..500 {
+            let (x, y) = (next(700) as f64 + 0.5, next(500) as f64 - 0.25);
+            let expected = index.nearest_linear(x, y).map(|(node, d)| (node.id, d));
+            let actual = index.nearest(x, y).map(|(node, d)| (node.id, d));
+            assert_eq!(actual, expected, "({}, {})", x, y);
+        }
+    }
+}
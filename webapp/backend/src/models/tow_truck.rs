@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use sqlx::FromRow;
 
 #[derive(FromRow, Clone, Debug)]
@@ -8,4 +9,6 @@ pub struct TowTruck {
     pub status: String,
     pub area_id: i32,
     pub node_id: i32,
+    // 最新位置を受信した時刻
+    pub last_seen: DateTime<Utc>,
 }
@@ -1,8 +1,9 @@
 use crate::errors::AppError;
+use crate::infrastructure::metrics::db_timer;
 use crate::models::user::{Dispatcher, User};
 use crate::{domains::auth_service::AuthRepository, models::user::Session};
 use sqlx::mysql::MySqlPool;
-use std::time::Instant;
+use tracing::instrument;
 
 #[derive(Debug)]
 pub struct AuthRepositoryImpl {
@@ -16,7 +17,9 @@ impl AuthRepositoryImpl {
 }
 
 impl AuthRepository for AuthRepositoryImpl {
+    #[instrument(skip(self))]
     async fn find_user_by_id(&self, id: i32) -> Result<Option<User>, AppError> {
+        let _timer = db_timer("auth_repository", "find_user_by_id");
         let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = ?")
             .bind(id)
             .fetch_optional(&self.pool)
@@ -25,25 +28,23 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(user)
     }
 
+    #[instrument(skip(self))]
     async fn find_user_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
-
-        let login_re = Instant::now();
-
+        let _timer = db_timer("auth_repository", "find_user_by_username");
         let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = ?")
             .bind(username)
             .fetch_optional(&self.pool)
             .await?;
 
-        let login_re_duration1 = login_re.elapsed();
-        println!("find_user_by_username 时间间隔: {:?}", login_re_duration1);
-
         Ok(user)
     }
     
+    #[instrument(skip(self))]
     async fn find_dispatcher_by_user_id(
         &self,
         user_id: i32,
     ) -> Result<Option<Dispatcher>, AppError> {
+        let _timer = db_timer("auth_repository", "find_dispatcher_by_user_id");
         let dispatcher =
             sqlx::query_as::<_, Dispatcher>("SELECT * FROM dispatchers WHERE user_id = ?")
                 .bind(user_id)
@@ -53,10 +54,12 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(dispatcher)
     }
 
+    #[instrument(skip(self))]
     async fn find_profile_image_name_by_user_id(
         &self,
         user_id: i32,
     ) -> Result<Option<String>, AppError> {
+        let _timer = db_timer("auth_repository", "find_profile_image_name_by_user_id");
         let profile_image_name = sqlx::query_scalar("SELECT profile_image FROM users WHERE id = ?")
             .bind(user_id)
             .fetch_optional(&self.pool)
@@ -65,12 +68,14 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(profile_image_name)
     }
 
+    #[instrument(skip(self, password))]
     async fn create_user(
         &self,
         username: &str,
         password: &str,
         role: &str,
     ) -> Result<(), AppError> {
+        let _timer = db_timer("auth_repository", "create_user");
         sqlx::query("INSERT INTO users (username, password, role) VALUES (?, ?, ?)")
             .bind(username)
             .bind(password)
@@ -81,7 +86,9 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(())
     }
 
+    #[instrument(skip(self, session_token))]
     async fn create_session(&self, user_id: i32, session_token: &str) -> Result<(), AppError> {
+        let _timer = db_timer("auth_repository", "create_session");
         sqlx::query("INSERT INTO sessions (user_id, session_token) VALUES (?, ?)")
             .bind(user_id)
             .bind(session_token)
@@ -91,7 +98,9 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(())
     }
 
+    #[instrument(skip(self, session_token))]
     async fn delete_session(&self, session_token: &str) -> Result<(), AppError> {
+        let _timer = db_timer("auth_repository", "delete_session");
         sqlx::query("DELETE FROM sessions WHERE session_token = ?")
             .bind(session_token)
             .execute(&self.pool)
@@ -100,10 +109,12 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(())
     }
 
+    #[instrument(skip(self, session_token))]
     async fn find_session_by_session_token(
         &self,
         session_token: &str,
     ) -> Result<Session, AppError> {
+        let _timer = db_timer("auth_repository", "find_session_by_session_token");
         let session =
             sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE session_token = ?")
                 .bind(session_token)
@@ -113,7 +124,34 @@ impl AuthRepository for AuthRepositoryImpl {
         Ok(session)
     }
 
+    #[instrument(skip(self, session_token))]
+    async fn find_user_by_valid_session_token(
+        &self,
+        session_token: &str,
+    ) -> Result<Option<User>, AppError> {
+        let _timer = db_timer("auth_repository", "find_user_by_valid_session_token");
+        let user = sqlx::query_as::<_, User>(
+            "SELECT
+                u.*
+            FROM
+                sessions s
+            JOIN
+                users u
+            ON
+                s.user_id = u.id
+            WHERE
+                s.session_token = ? AND s.is_valid",
+        )
+        .bind(session_token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[instrument(skip(self))]
     async fn find_dispatcher_by_id(&self, id: i32) -> Result<Option<Dispatcher>, AppError> {
+        let _timer = db_timer("auth_repository", "find_dispatcher_by_id");
         let dispatcher = sqlx::query_as::<_, Dispatcher>("SELECT * FROM dispatchers WHERE id = ?")
             .bind(id)
             .fetch_optional(&self.pool)
@@ -124,7 +162,9 @@ impl AuthRepository for AuthRepositoryImpl {
 
     
 
+    #[instrument(skip(self))]
     async fn create_dispatcher(&self, user_id: i32, area_id: i32) -> Result<(), AppError> {
+        let _timer = db_timer("auth_repository", "create_dispatcher");
         sqlx::query("INSERT INTO dispatchers (user_id, area_id) VALUES (?, ?)")
             .bind(user_id)
             .bind(area_id)
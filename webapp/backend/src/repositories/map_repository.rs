@@ -2,8 +2,11 @@ use sqlx::MySqlPool;
 
 use crate::{
     domains::map_service::MapRepository,
+    infrastructure::metrics::db_timer,
     models::graph::{Edge, Node},
+    repositories::query_builder::{QueryBuilder, SortOrder},
 };
+use tracing::instrument;
 
 #[derive(Debug)]
 pub struct MapRepositoryImpl {
@@ -17,75 +20,56 @@ impl MapRepositoryImpl {
 }
 
 impl MapRepository for MapRepositoryImpl {
+    #[instrument(skip(self))]
     async fn get_all_nodes(&self, area_id: Option<i32>) -> Result<Vec<Node>, sqlx::Error> {
-        let where_clause = match area_id {
-            Some(_) => "WHERE area_id = ?",
-            None => "",
-        };
-
-        let sql = format!(
+        let _timer = db_timer("map_repository", "get_all_nodes");
+        let mut builder = QueryBuilder::new(
             "SELECT
-                * 
+                *
             FROM
-                nodes
-            {}
-            ORDER BY
-                id",
-            where_clause
+                nodes",
         );
+        builder
+            .and_where_opt("area_id = ?", area_id)
+            .order_by("id", SortOrder::Asc);
 
-        let nodes = match area_id {
-            Some(area_id) => {
-                sqlx::query_as::<_, Node>(&sql)
-                    .bind(area_id)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            None => {
-                sqlx::query_as::<_, Node>(&sql)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-        };
+        let sql = builder.sql();
+        let nodes = builder
+            .build_query_as::<Node>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(nodes)
     }
 
+    #[instrument(skip(self))]
     async fn get_all_edges(&self, area_id: Option<i32>) -> Result<Vec<Edge>, sqlx::Error> {
-        let where_clause = match area_id {
-            Some(_) => "JOIN nodes n ON e.node_a_id = n.id WHERE n.area_id = ?",
-            None => "",
-        };
-
-        let sql = format!(
+        let _timer = db_timer("map_repository", "get_all_edges");
+        let mut builder = QueryBuilder::new(
             "SELECT
                 e.node_a_id,
                 e.node_b_id,
                 e.weight
             FROM
-                edges e
-            {}",
-            where_clause
+                edges e",
         );
+        if area_id.is_some() {
+            builder.join("JOIN nodes n ON e.node_a_id = n.id");
+        }
+        builder.and_where_opt("n.area_id = ?", area_id);
 
-        let edges = match area_id {
-            Some(area_id) => {
-                sqlx::query_as::<_, Edge>(&sql)
-                    .bind(area_id)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            None => {
-                sqlx::query_as::<_, Edge>(&sql)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-        };
+        let sql = builder.sql();
+        let edges = builder
+            .build_query_as::<Edge>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(edges)
     }
 
+    #[instrument(skip(self))]
     async fn get_area_id_by_node_id(&self, node_id: i32) -> Result<i32, sqlx::Error> {
+        let _timer = db_timer("map_repository", "get_area_id_by_node_id");
         let area_id = sqlx::query_scalar("SELECT area_id FROM nodes WHERE id = ?")
             .bind(node_id)
             .fetch_one(&self.pool)
@@ -94,12 +78,38 @@ impl MapRepository for MapRepositoryImpl {
         Ok(area_id)
     }
 
+    // (node_id, area_id) の組を返す。存在しないノードは含まれない
+    #[instrument(skip(self))]
+    async fn get_area_ids_by_node_ids(
+        &self,
+        node_ids: &[i32],
+    ) -> Result<Vec<(i32, i32)>, sqlx::Error> {
+        let _timer = db_timer("map_repository", "get_area_ids_by_node_ids");
+        let mut builder = QueryBuilder::new(
+            "SELECT
+                id, area_id
+            FROM
+                nodes",
+        );
+        builder.and_where_in("id", node_ids.iter().copied());
+
+        let sql = builder.sql();
+        let area_ids = builder
+            .build_query_as::<(i32, i32)>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(area_ids)
+    }
+
+    #[instrument(skip(self))]
     async fn update_edge(
         &self,
         node_a_id: i32,
         node_b_id: i32,
         weight: i32,
     ) -> Result<(), sqlx::Error> {
+        let _timer = db_timer("map_repository", "update_edge");
         sqlx::query("UPDATE edges SET weight = ? WHERE (node_a_id = ? AND node_b_id = ?) OR (node_a_id = ? AND node_b_id = ?)")
             .bind(weight)
             .bind(node_a_id)
@@ -1,8 +1,14 @@
-use crate::domains::order_service::OrderRepository;
-use crate::errors::AppError;
-use crate::models::order::Order;
+use crate::domains::order_service::{OrderRepository, OrderSort};
+use crate::errors::{AppError, ErrorCode};
+use crate::infrastructure::metrics::db_timer;
+use crate::models::order::{
+    AssignmentChange, NewOrderCancellation, Order, OrderAssignment, TowTruckWorkload,
+    UnescalatedOrder,
+};
+use crate::repositories::query_builder::{placeholders, Arg, QueryBuilder, SortOrder};
 use chrono::{DateTime, Utc};
 use sqlx::mysql::MySqlPool;
+use tracing::instrument;
 
 #[derive(Debug)]
 pub struct OrderRepositoryImpl {
@@ -16,7 +22,9 @@ impl OrderRepositoryImpl {
 }
 
 impl OrderRepository for OrderRepositoryImpl {
+    #[instrument(skip(self))]
     async fn find_order_by_id(&self, id: i32) -> Result<Order, AppError> {
+        let _timer = db_timer("order_repository", "find_order_by_id");
         let order = sqlx::query_as::<_, Order>(
             "SELECT 
                 *
@@ -32,7 +40,9 @@ impl OrderRepository for OrderRepositoryImpl {
         Ok(order)
     }
 
+    #[instrument(skip(self))]
     async fn update_order_status(&self, order_id: i32, status: &str) -> Result<(), AppError> {
+        let _timer = db_timer("order_repository", "update_order_status");
         sqlx::query("UPDATE orders SET status = ? WHERE id = ?")
             .bind(status)
             .bind(order_id)
@@ -42,166 +52,100 @@ impl OrderRepository for OrderRepositoryImpl {
         Ok(())
     }
 
+    #[instrument(skip(self))]
     async fn get_paginated_orders(
         &self,
         page: i32,
         page_size: i32,
-        sort_by: Option<String>,
-        sort_order: Option<String>,
+        sort: OrderSort<'_>,
         status: Option<String>,
         area: Option<i32>,
     ) -> Result<Vec<Order>, AppError> {
-        /*
-        let offset = page * page_size;
-        let order_clause = format!(
-            "ORDER BY {} {}",
-            match sort_by.as_deref() {
-                Some("car_value") => "o.car_value",
-                Some("status") => "o.status",
-                Some("order_time") => "o.order_time",
-                _ => "o.order_time",
-            },
-            match sort_order.as_deref() {
-                Some("DESC") => "DESC",
-                Some("desc") => "DESC",
-                _ => "ASC",
-            }
-        );
-
-        let where_clause = match (status.clone(), area) {
-            (Some(_), Some(_)) => "WHERE o.status = ? AND n.area_id = ?".to_string(),
-            (None, Some(_)) => "WHERE n.area_id = ?".to_string(),
-            (Some(_), None) => "WHERE o.status = ?".to_string(),
-            _ => "".to_string(),
-        };
-
-        let sql = format!(
-            "SELECT 
-                o.id, 
-                o.client_id, 
-                o.dispatcher_id, 
-                o.tow_truck_id, 
-                o.status, 
-                o.node_id, 
-                o.car_value, 
-                o.order_time, 
-                o.completed_time
-            FROM
-                orders o
-            JOIN
-                nodes n
-            ON 
-                o.node_id = n.id
-            {} 
-            {} 
-            LIMIT ? 
-            OFFSET ?",
-            where_clause, order_clause
-        );
-        */
-        let offset = page * page_size;
-
-        // Construct the ORDER BY clause safely
-        let order_clause = format!(
-            "ORDER BY {} {}",
-            match sort_by.as_deref() {
-                Some("car_value") => "o.car_value",
-                Some("status") => "o.status",
-                Some("order_time") => "o.order_time",
-                _ => "o.order_time",
-            },
-            match sort_order.as_deref() {
-                Some("DESC") | Some("desc") => "DESC",
-                _ => "ASC",
-            }
-        );
+        let _timer = db_timer("order_repository", "get_paginated_orders");
 
-        // Conditionally include JOIN and WHERE clauses
-        let (join_clause, where_clause) = match (status.clone(), area) {
-            (Some(_), Some(_)) => (
-                "JOIN nodes n ON o.node_id = n.id",
-                "WHERE o.status = ? AND n.area_id = ?".to_string(),
-            ),
-            (None, Some(_)) => (
-                "JOIN nodes n ON o.node_id = n.id",
-                "WHERE n.area_id = ?".to_string(),
-            ),
-            (Some(_), None) => (
-                "", // No JOIN needed when area is not specified
-                "WHERE o.status = ?".to_string(),
-            ),
-            _ => (
-                "", // No JOIN or WHERE clause needed
-                "".to_string(),
-            ),
-        };
-
-        // Build the final SQL query
-        let sql = format!(
-            "SELECT 
-                o.id, 
-                o.client_id, 
-                o.dispatcher_id, 
-                o.tow_truck_id, 
-                o.status, 
-                o.node_id, 
-                o.car_value, 
-                o.order_time, 
-                o.completed_time
+        let mut builder = QueryBuilder::new(
+            "SELECT
+                o.id,
+                o.client_id,
+                o.dispatcher_id,
+                o.tow_truck_id,
+                o.status,
+                o.node_id,
+                o.car_value,
+                o.order_time,
+                o.completed_time,
+                o.escalated_at
             FROM
-                orders o
-            {}  -- Conditionally include JOIN
-            {}  -- Conditionally include WHERE
-            {}  -- ORDER BY clause
-            LIMIT ? 
-            OFFSET ?",
-            join_clause, where_clause, order_clause
+                orders o",
         );
-
-        let orders = match (status, area) {
-            (Some(status), Some(area)) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(status)
-                    .bind(area)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-            (None, Some(area)) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(area)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
+        // エリアで絞り込む場合のみ nodes を JOIN する
+        if area.is_some() {
+            builder.join("JOIN nodes n ON o.node_id = n.id");
+        }
+        builder
+            .and_where_opt("o.status = ?", status)
+            .and_where_opt("n.area_id = ?", area);
+        match sort {
+            OrderSort::Column {
+                sort_by,
+                sort_order,
+            } => {
+                let sort_column = match sort_by.as_str() {
+                    "car_value" => "o.car_value",
+                    "status" => "o.status",
+                    _ => "o.order_time",
+                };
+                builder.order_by(sort_column, SortOrder::parse(sort_order.as_deref()));
             }
-            (Some(status), None) => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(status)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
+            OrderSort::Priority {
+                config: priority,
+                now,
+                sort_order,
+            } => {
+                // 指定がない場合は優先度の高い順
+                let order = match sort_order {
+                    Some(_) => SortOrder::parse(sort_order.as_deref()),
+                    None => SortOrder::Desc,
+                };
+                // PriorityConfig::score と同じ式。変える場合は order_service のテストの値も合わせる
+                builder.order_by_expr(
+                    "(? * o.car_value
+                    + ? * TIMESTAMPDIFF(SECOND, o.order_time, COALESCE(o.completed_time, ?)) / 60
+                    + IF(o.car_value >= ?, ?, 0)
+                    + IF(o.escalated_at IS NULL, 0, ?))",
+                    vec![
+                        Arg::Float(priority.car_value_weight),
+                        Arg::Float(priority.wait_weight),
+                        Arg::DateTime(now),
+                        Arg::Float(priority.high_value_threshold),
+                        Arg::Float(priority.high_value_bonus),
+                        Arg::Float(priority.escalation_bonus),
+                    ],
+                    order,
+                );
             }
-            _ => {
-                sqlx::query_as::<_, Order>(&sql)
-                    .bind(page_size)
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await?
-            }
-        };
+        }
+        // 検証済みの値でも、直接呼ばれた場合に備えて桁あふれは 400 にする
+        let offset = page.checked_mul(page_size).ok_or(AppError::BadRequest)?;
+        builder.limit_offset(page_size, offset);
+
+        let sql = builder.sql();
+        let orders = builder
+            .build_query_as::<Order>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(orders)
     }
 
+    #[instrument(skip(self))]
     async fn create_order(
         &self,
         client_id: i32,
         node_id: i32,
         car_value: f64,
     ) -> Result<(), AppError> {
+        let _timer = db_timer("order_repository", "create_order");
         sqlx::query("INSERT INTO orders (client_id, node_id, status, car_value) VALUES (?, ?, 'pending', ?)")
             .bind(client_id)
             .bind(node_id)
@@ -212,37 +156,689 @@ impl OrderRepository for OrderRepositoryImpl {
         Ok(())
     }
 
-    async fn update_order_dispatched(
+    #[instrument(skip(self))]
+    async fn dispatch_order(
         &self,
-        id: i32,
+        order_id: i32,
         dispatcher_id: i32,
         tow_truck_id: i32,
+        completed_time: DateTime<Utc>,
     ) -> Result<(), AppError> {
+        let _timer = db_timer("order_repository", "dispatch_order");
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES (?, ?, ?)")
+            .bind(order_id)
+            .bind(tow_truck_id)
+            .bind(completed_time)
+            .execute(&mut tx)
+            .await?;
+
         sqlx::query(
             "UPDATE orders SET dispatcher_id = ?, tow_truck_id = ?, status = 'dispatched' WHERE id = ?",
         )
         .bind(dispatcher_id)
         .bind(tow_truck_id)
-        .bind(id)
-        .execute(&self.pool)
+        .bind(order_id)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO order_assignments (order_id, tow_truck_id, assigned_by, assigned_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(order_id)
+        .bind(tow_truck_id)
+        .bind(dispatcher_id)
+        .bind(Utc::now())
+        .execute(&mut tx)
         .await?;
 
+        sqlx::query("UPDATE tow_trucks SET status = 'busy' WHERE id = ?")
+            .bind(tow_truck_id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
         Ok(())
     }
 
-    async fn create_completed_order(
+    #[instrument(skip(self))]
+    async fn find_dispatched_orders(
         &self,
-        order_id: i32,
-        tow_truck_id: i32,
-        completed_time: DateTime<Utc>,
+        area_id: Option<i32>,
+        tow_truck_ids: Option<&[i32]>,
+    ) -> Result<Vec<Order>, AppError> {
+        let _timer = db_timer("order_repository", "find_dispatched_orders");
+
+        let mut builder = QueryBuilder::new(
+            "SELECT
+                o.id,
+                o.client_id,
+                o.dispatcher_id,
+                o.tow_truck_id,
+                o.status,
+                o.node_id,
+                o.car_value,
+                o.order_time,
+                o.completed_time,
+                o.escalated_at
+            FROM
+                orders o",
+        );
+        if area_id.is_some() {
+            builder.join("JOIN nodes n ON o.node_id = n.id");
+        }
+        builder
+            .and_where("o.status = ?", "dispatched")
+            .and_where_opt("n.area_id = ?", area_id);
+        if let Some(tow_truck_ids) = tow_truck_ids {
+            builder.and_where_in("o.tow_truck_id", tow_truck_ids.iter().copied());
+        }
+
+        let sql = builder.sql();
+        let orders = builder
+            .build_query_as::<Order>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(orders)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_pending_orders(&self, area_id: i32) -> Result<Vec<Order>, AppError> {
+        let _timer = db_timer("order_repository", "find_pending_orders");
+        let orders = sqlx::query_as::<_, Order>(
+            "SELECT
+                o.id,
+                o.client_id,
+                o.dispatcher_id,
+                o.tow_truck_id,
+                o.status,
+                o.node_id,
+                o.car_value,
+                o.order_time,
+                o.completed_time,
+                o.escalated_at
+            FROM
+                orders o
+            JOIN
+                nodes n ON o.node_id = n.id
+            WHERE
+                o.status = 'pending'
+                AND n.area_id = ?",
+        )
+        .bind(area_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(orders)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_tow_truck_workloads(
+        &self,
+        tow_truck_ids: &[i32],
+        since: DateTime<Utc>,
+    ) -> Result<Vec<TowTruckWorkload>, AppError> {
+        let _timer = db_timer("order_repository", "get_tow_truck_workloads");
+        if tow_truck_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // 完了時刻のない依頼は受付時刻を担当した時刻とみなす
+        let mut builder = QueryBuilder::with_args(
+            "SELECT
+                tow_truck_id,
+                MAX(COALESCE(completed_time, order_time)) AS last_active_time,
+                CAST(SUM(order_time >= ?) AS SIGNED) AS recent_orders
+            FROM
+                orders",
+            vec![Arg::DateTime(since)],
+        );
+        builder
+            .and_where_in("tow_truck_id", tow_truck_ids.iter().copied())
+            .group_by("tow_truck_id");
+
+        let sql = builder.sql();
+        let workloads = builder
+            .build_query_as::<TowTruckWorkload>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(workloads)
+    }
+
+    #[instrument(skip(self))]
+    async fn dispatch_orders(
+        &self,
+        area_id: i32,
+        dispatcher_id: Option<i32>,
+        assignments: &[(i32, i32)],
+        dispatched_time: DateTime<Utc>,
     ) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES (?, ?, ?)")
-            .bind(order_id)
+        let _timer = db_timer("order_repository", "dispatch_orders");
+        let mut tx = self.pool.begin().await?;
+
+        // 計画を作ってから受け入れるまでに、他の配車担当者が割り当てた可能性がある
+        let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM orders o");
+        builder
+            .join("JOIN nodes n ON o.node_id = n.id")
+            .and_where_in("o.id", assignments.iter().map(|(order_id, _)| *order_id))
+            .and_where("o.status = ?", "pending")
+            .and_where("n.area_id = ?", area_id)
+            .for_update();
+        let sql = builder.sql();
+        let pending_orders = builder
+            .build_query_scalar::<i64>(&sql)
+            .fetch_one(&mut tx)
+            .await?;
+
+        let mut builder = QueryBuilder::new("SELECT COUNT(*) FROM tow_trucks");
+        builder
+            .and_where_in(
+                "id",
+                assignments.iter().map(|(_, tow_truck_id)| *tow_truck_id),
+            )
+            .and_where("status = ?", "available")
+            .and_where("area_id = ?", area_id)
+            .for_update();
+        let sql = builder.sql();
+        let available_tow_trucks = builder
+            .build_query_scalar::<i64>(&sql)
+            .fetch_one(&mut tx)
+            .await?;
+
+        if pending_orders != assignments.len() as i64
+            || available_tow_trucks != assignments.len() as i64
+        {
+            return Err(AppError::Coded(ErrorCode::PlanOutdated));
+        }
+
+        let sql = format!(
+            "INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES {}",
+            placeholders("(?, ?, ?)", assignments.len())
+        );
+        let mut query = sqlx::query(&sql);
+        for (order_id, tow_truck_id) in assignments {
+            query = query
+                .bind(order_id)
+                .bind(tow_truck_id)
+                .bind(dispatched_time);
+        }
+        query.execute(&mut tx).await?;
+
+        for (order_id, tow_truck_id) in assignments {
+            sqlx::query(
+                "UPDATE orders SET dispatcher_id = ?, tow_truck_id = ?, status = 'dispatched' WHERE id = ?",
+            )
+            .bind(dispatcher_id)
             .bind(tow_truck_id)
-            .bind(completed_time)
-            .execute(&self.pool)
+            .bind(order_id)
+            .execute(&mut tx)
             .await?;
+        }
+
+        let sql = format!(
+            "INSERT INTO order_assignments (order_id, tow_truck_id, assigned_by, assigned_at) VALUES {}",
+            placeholders("(?, ?, ?, ?)", assignments.len())
+        );
+        let mut query = sqlx::query(&sql);
+        for (order_id, tow_truck_id) in assignments {
+            query = query
+                .bind(order_id)
+                .bind(tow_truck_id)
+                .bind(dispatcher_id)
+                .bind(dispatched_time);
+        }
+        query.execute(&mut tx).await?;
+
+        let mut builder = QueryBuilder::new("UPDATE tow_trucks SET status = 'busy'");
+        builder.and_where_in(
+            "id",
+            assignments.iter().map(|(_, tow_truck_id)| *tow_truck_id),
+        );
+        let sql = builder.sql();
+        builder.build_query(&sql).execute(&mut tx).await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn find_unescalated_pending_orders(
+        &self,
+        received_before: DateTime<Utc>,
+    ) -> Result<Vec<UnescalatedOrder>, AppError> {
+        let _timer = db_timer("order_repository", "find_unescalated_pending_orders");
+        let orders = sqlx::query_as::<_, UnescalatedOrder>(
+            "SELECT
+                o.id,
+                n.area_id,
+                o.order_time
+            FROM
+                orders o
+            JOIN
+                nodes n ON o.node_id = n.id
+            WHERE
+                o.status = 'pending'
+                AND o.escalated_at IS NULL
+                AND o.order_time <= ?",
+        )
+        .bind(received_before)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(orders)
+    }
+
+    #[instrument(skip(self))]
+    async fn mark_orders_escalated(
+        &self,
+        order_ids: &[i32],
+        escalated_at: DateTime<Utc>,
+    ) -> Result<(), AppError> {
+        let _timer = db_timer("order_repository", "mark_orders_escalated");
+        if order_ids.is_empty() {
+            return Ok(());
+        }
+
+        let mut builder = QueryBuilder::with_args(
+            "UPDATE orders SET escalated_at = ?",
+            vec![Arg::DateTime(escalated_at)],
+        );
+        builder
+            .and_where_in("id", order_ids.iter().copied())
+            .and_where_raw("escalated_at IS NULL");
+        let sql = builder.sql();
+        builder.build_query(&sql).execute(&self.pool).await?;
 
         Ok(())
     }
+
+    #[instrument(skip(self))]
+    async fn cancel_order(
+        &self,
+        order_id: i32,
+        cancellation: &NewOrderCancellation,
+    ) -> Result<Option<i32>, AppError> {
+        let _timer = db_timer("order_repository", "cancel_order");
+        let mut tx = self.pool.begin().await?;
+
+        let (status, tow_truck_id) = sqlx::query_as::<_, (String, Option<i32>)>(
+            "SELECT status, tow_truck_id FROM orders WHERE id = ? FOR UPDATE",
+        )
+        .bind(order_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::Coded(ErrorCode::OrderNotFound))?;
+
+        let released_tow_truck_id = match status.as_str() {
+            "pending" => None,
+            "dispatched" => tow_truck_id,
+            _ => return Err(AppError::Coded(ErrorCode::OrderNotCancellable)),
+        };
+
+        sqlx::query("UPDATE orders SET status = 'cancelled' WHERE id = ?")
+            .bind(order_id)
+            .execute(&mut tx)
+            .await?;
+
+        if let Some(tow_truck_id) = released_tow_truck_id {
+            sqlx::query("UPDATE tow_trucks SET status = 'available' WHERE id = ?")
+                .bind(tow_truck_id)
+                .execute(&mut tx)
+                .await?;
+            // 解放しないと、このレッカー車を再び割り当てられない
+            sqlx::query(
+                "UPDATE completed_orders SET released_at = ? WHERE order_id = ? AND released_at IS NULL",
+            )
+            .bind(cancellation.cancelled_at)
+            .bind(order_id)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        sqlx::query(
+            "UPDATE order_assignments SET unassigned_at = ?, unassign_reason = 'order_cancelled'
+            WHERE order_id = ? AND unassigned_at IS NULL",
+        )
+        .bind(cancellation.cancelled_at)
+        .bind(order_id)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO order_cancellations
+                (order_id, cancelled_by, cancelled_by_role, reason, note, released_tow_truck_id, cancelled_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(order_id)
+        .bind(cancellation.cancelled_by)
+        .bind(&cancellation.cancelled_by_role)
+        .bind(&cancellation.reason)
+        .bind(&cancellation.note)
+        .bind(released_tow_truck_id)
+        .bind(cancellation.cancelled_at)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(released_tow_truck_id)
+    }
+
+    #[instrument(skip(self))]
+    async fn change_assignment(
+        &self,
+        order_id: i32,
+        area_id: i32,
+        tow_truck_id: Option<i32>,
+        change: &AssignmentChange,
+    ) -> Result<i32, AppError> {
+        let _timer = db_timer("order_repository", "change_assignment");
+        let mut tx = self.pool.begin().await?;
+
+        let (status, previous_tow_truck_id) = sqlx::query_as::<_, (String, Option<i32>)>(
+            "SELECT status, tow_truck_id FROM orders WHERE id = ? FOR UPDATE",
+        )
+        .bind(order_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(AppError::Coded(ErrorCode::OrderNotFound))?;
+        let previous_tow_truck_id = match (status.as_str(), previous_tow_truck_id) {
+            ("dispatched", Some(previous_tow_truck_id)) => previous_tow_truck_id,
+            _ => return Err(AppError::Coded(ErrorCode::OrderNotDispatched)),
+        };
+        if change
+            .expected_tow_truck_id
+            .is_some_and(|expected| expected != previous_tow_truck_id)
+        {
+            return Err(AppError::Coded(ErrorCode::OfferNotPending));
+        }
+
+        match tow_truck_id {
+            Some(tow_truck_id) => {
+                let available = sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM tow_trucks
+                    WHERE id = ? AND status = 'available' AND area_id = ?
+                    FOR UPDATE",
+                )
+                .bind(tow_truck_id)
+                .bind(area_id)
+                .fetch_one(&mut tx)
+                .await?;
+                if available == 0 {
+                    return Err(AppError::Coded(ErrorCode::TowTruckUnavailable));
+                }
+
+                // 解放されていない行のレッカー車は重複できないので、重複は新しいレッカー車が割り当て済みであることを表す
+                sqlx::query(
+                    "UPDATE completed_orders SET released_at = ? WHERE order_id = ? AND released_at IS NULL",
+                )
+                .bind(change.changed_at)
+                .bind(order_id)
+                .execute(&mut tx)
+                .await?;
+                sqlx::query("INSERT INTO completed_orders (order_id, tow_truck_id, completed_time) VALUES (?, ?, ?)")
+                    .bind(order_id)
+                    .bind(tow_truck_id)
+                    .bind(change.changed_at)
+                    .execute(&mut tx)
+                    .await?;
+                sqlx::query("UPDATE orders SET dispatcher_id = COALESCE(?, dispatcher_id), tow_truck_id = ? WHERE id = ?")
+                    .bind(change.dispatcher_id)
+                    .bind(tow_truck_id)
+                    .bind(order_id)
+                    .execute(&mut tx)
+                    .await?;
+                sqlx::query("UPDATE tow_trucks SET status = 'busy' WHERE id = ?")
+                    .bind(tow_truck_id)
+                    .execute(&mut tx)
+                    .await?;
+            }
+            None => {
+                sqlx::query(
+                    "UPDATE completed_orders SET released_at = ? WHERE order_id = ? AND released_at IS NULL",
+                )
+                .bind(change.changed_at)
+                .bind(order_id)
+                .execute(&mut tx)
+                .await?;
+                sqlx::query(
+                    "UPDATE orders SET tow_truck_id = NULL, status = 'pending' WHERE id = ?",
+                )
+                .bind(order_id)
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        sqlx::query("UPDATE tow_trucks SET status = 'available' WHERE id = ?")
+            .bind(previous_tow_truck_id)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query(
+            "UPDATE order_assignments SET unassigned_at = ?, unassigned_by = ?, unassign_reason = ?
+            WHERE order_id = ? AND unassigned_at IS NULL",
+        )
+        .bind(change.changed_at)
+        .bind(change.dispatcher_id)
+        .bind(&change.reason)
+        .bind(order_id)
+        .execute(&mut tx)
+        .await?;
+        if let Some(tow_truck_id) = tow_truck_id {
+            sqlx::query(
+                "INSERT INTO order_assignments (order_id, tow_truck_id, assigned_by, assigned_at) VALUES (?, ?, ?, ?)",
+            )
+            .bind(order_id)
+            .bind(tow_truck_id)
+            .bind(change.dispatcher_id)
+            .bind(change.changed_at)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(previous_tow_truck_id)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_order_assignments(&self, order_id: i32) -> Result<Vec<OrderAssignment>, AppError> {
+        let _timer = db_timer("order_repository", "get_order_assignments");
+        let assignments = sqlx::query_as::<_, OrderAssignment>(
+            "SELECT
+                id,
+                order_id,
+                tow_truck_id,
+                assigned_by,
+                assigned_at,
+                unassigned_at,
+                unassigned_by,
+                unassign_reason,
+                offer_status,
+                responded_at,
+                en_route_at,
+                on_site_at,
+                completed_at
+            FROM
+                order_assignments
+            WHERE
+                order_id = ?
+            ORDER BY
+                assigned_at, id",
+        )
+        .bind(order_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(assignments)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_current_assignment(
+        &self,
+        order_id: i32,
+    ) -> Result<Option<OrderAssignment>, AppError> {
+        let _timer = db_timer("order_repository", "find_current_assignment");
+        let assignment = sqlx::query_as::<_, OrderAssignment>(
+            "SELECT
+                id,
+                order_id,
+                tow_truck_id,
+                assigned_by,
+                assigned_at,
+                unassigned_at,
+                unassigned_by,
+                unassign_reason,
+                offer_status,
+                responded_at,
+                en_route_at,
+                on_site_at,
+                completed_at
+            FROM
+                order_assignments
+            WHERE
+                order_id = ? AND unassigned_at IS NULL",
+        )
+        .bind(order_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(assignment)
+    }
+
+    #[instrument(skip(self))]
+    async fn respond_to_offer(
+        &self,
+        assignment_id: i32,
+        offer_status: &str,
+        responded_at: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let _timer = db_timer("order_repository", "respond_to_offer");
+        let result = sqlx::query(
+            "UPDATE order_assignments SET offer_status = ?, responded_at = ?
+            WHERE id = ? AND offer_status = 'offered' AND unassigned_at IS NULL",
+        )
+        .bind(offer_status)
+        .bind(responded_at)
+        .bind(assignment_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(skip(self))]
+    async fn find_unaccepted_offers(
+        &self,
+        offered_before: DateTime<Utc>,
+    ) -> Result<Vec<OrderAssignment>, AppError> {
+        let _timer = db_timer("order_repository", "find_unaccepted_offers");
+        let assignments = sqlx::query_as::<_, OrderAssignment>(
+            "SELECT
+                a.id,
+                a.order_id,
+                a.tow_truck_id,
+                a.assigned_by,
+                a.assigned_at,
+                a.unassigned_at,
+                a.unassigned_by,
+                a.unassign_reason,
+                a.offer_status,
+                a.responded_at,
+                a.en_route_at,
+                a.on_site_at,
+                a.completed_at
+            FROM
+                order_assignments a
+            JOIN
+                orders o ON a.order_id = o.id
+            WHERE
+                a.unassigned_at IS NULL
+                AND o.status = 'dispatched'
+                AND (
+                    a.offer_status IN ('declined', 'expired')
+                    OR (a.offer_status = 'offered' AND a.assigned_at < ?)
+                )
+            ORDER BY
+                a.assigned_at, a.id",
+        )
+        .bind(offered_before)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(assignments)
+    }
+
+    #[instrument(skip(self))]
+    async fn advance_job(
+        &self,
+        assignment_id: i32,
+        order_id: i32,
+        status: &str,
+        at: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let _timer = db_timer("order_repository", "advance_job");
+        // 承諾済みで、直前の段階まで進んでいる現在の割り当てだけを更新する
+        let sql = match status {
+            "en_route" => {
+                "UPDATE order_assignments SET en_route_at = ?
+                WHERE id = ? AND unassigned_at IS NULL AND offer_status = 'accepted'
+                    AND en_route_at IS NULL"
+            }
+            "on_site" => {
+                "UPDATE order_assignments SET on_site_at = ?
+                WHERE id = ? AND unassigned_at IS NULL AND offer_status = 'accepted'
+                    AND en_route_at IS NOT NULL AND on_site_at IS NULL"
+            }
+            "completed" => {
+                "UPDATE order_assignments SET completed_at = ?
+                WHERE id = ? AND unassigned_at IS NULL AND offer_status = 'accepted'
+                    AND on_site_at IS NOT NULL AND completed_at IS NULL"
+            }
+            _ => return Ok(false),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(sql)
+            .bind(at)
+            .bind(assignment_id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        // 完了したらキャンセルと同じくレッカー車を解放する
+        if status == "completed" {
+            sqlx::query(
+                "UPDATE orders SET status = 'completed', completed_time = ?
+                WHERE id = ? AND status = 'dispatched'",
+            )
+            .bind(at)
+            .bind(order_id)
+            .execute(&mut tx)
+            .await?;
+            sqlx::query(
+                "UPDATE tow_trucks t JOIN order_assignments a ON t.id = a.tow_truck_id
+                SET t.status = 'available'
+                WHERE a.id = ?",
+            )
+            .bind(assignment_id)
+            .execute(&mut tx)
+            .await?;
+            // 解放しないと、このレッカー車を再び割り当てられない
+            sqlx::query(
+                "UPDATE completed_orders SET released_at = ? WHERE order_id = ? AND released_at IS NULL",
+            )
+            .bind(at)
+            .bind(order_id)
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(true)
+    }
 }
@@ -0,0 +1,426 @@
+use chrono::{DateTime, Utc};
+use sqlx::mysql::{MySql, MySqlArguments, MySqlRow};
+use sqlx::query::{Query, QueryAs, QueryScalar};
+use sqlx::{Arguments, FromRow};
+
+// SQL の断片は &'static str しか受け取らないので、ユーザー入力は必ずバインド変数として渡される
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Float(f64),
+    Text(String),
+    DateTime(DateTime<Utc>),
+}
+
+impl From<i32> for Arg {
+    fn from(value: i32) -> Self {
+        Arg::Int(value)
+    }
+}
+
+impl From<f64> for Arg {
+    fn from(value: f64) -> Self {
+        Arg::Float(value)
+    }
+}
+
+impl From<String> for Arg {
+    fn from(value: String) -> Self {
+        Arg::Text(value)
+    }
+}
+
+impl From<DateTime<Utc>> for Arg {
+    fn from(value: DateTime<Utc>) -> Self {
+        Arg::DateTime(value)
+    }
+}
+
+impl From<&str> for Arg {
+    fn from(value: &str) -> Self {
+        Arg::Text(value.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    // DESC / desc 以外はすべて昇順として扱う
+    pub fn parse(value: Option<&str>) -> Self {
+        match value {
+            Some("DESC") | Some("desc") => SortOrder::Desc,
+            _ => SortOrder::Asc,
+        }
+    }
+
+    fn as_sql(&self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+}
+
+// select は WHERE より前の部分。UPDATE 文の UPDATE ... SET ... も渡せる
+#[derive(Debug)]
+pub struct QueryBuilder {
+    select: &'static str,
+    joins: Vec<&'static str>,
+    conditions: Vec<String>,
+    group_by: Option<&'static str>,
+    order_by: Vec<(&'static str, SortOrder)>,
+    limit: Option<(i32, i32)>,
+    for_update: bool,
+    args: Vec<Arg>,
+    // ORDER BY の式に含まれるバインド変数。WHERE と LIMIT の間に並ぶ
+    order_args: Vec<Arg>,
+}
+
+impl QueryBuilder {
+    pub fn new(select: &'static str) -> Self {
+        Self::with_args(select, Vec::new())
+    }
+
+    // select 自体にプレースホルダを含む場合。args は WHERE の値より前に並ぶ
+    pub fn with_args(select: &'static str, args: impl IntoIterator<Item = Arg>) -> Self {
+        let args: Vec<Arg> = args.into_iter().collect();
+        debug_assert_eq!(select.matches('?').count(), args.len());
+        QueryBuilder {
+            select,
+            joins: Vec::new(),
+            conditions: Vec::new(),
+            group_by: None,
+            order_by: Vec::new(),
+            limit: None,
+            for_update: false,
+            args,
+            order_args: Vec::new(),
+        }
+    }
+
+    pub fn join(&mut self, join: &'static str) -> &mut Self {
+        self.joins.push(join);
+        self
+    }
+
+    // バインド変数を持たない固定の条件
+    pub fn and_where_raw(&mut self, condition: &'static str) -> &mut Self {
+        debug_assert!(!condition.contains('?'));
+        self.conditions.push(condition.to_string());
+        self
+    }
+
+    // condition にはプレースホルダ ? をちょうど1つ含める
+    pub fn and_where(&mut self, condition: &'static str, arg: impl Into<Arg>) -> &mut Self {
+        debug_assert_eq!(condition.matches('?').count(), 1);
+        self.conditions.push(condition.to_string());
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn and_where_opt<T: Into<Arg>>(
+        &mut self,
+        condition: &'static str,
+        arg: Option<T>,
+    ) -> &mut Self {
+        if let Some(arg) = arg {
+            self.and_where(condition, arg);
+        }
+        self
+    }
+
+    // 値の数だけプレースホルダを並べる。空の場合はどの行にも一致しない
+    pub fn and_where_in<T: Into<Arg>>(
+        &mut self,
+        column: &'static str,
+        values: impl IntoIterator<Item = T>,
+    ) -> &mut Self {
+        let before = self.args.len();
+        self.args.extend(values.into_iter().map(Into::into));
+        let count = self.args.len() - before;
+
+        if count == 0 {
+            self.conditions.push("1 = 0".to_string());
+        } else {
+            self.conditions
+                .push(format!("{} IN ({})", column, placeholders("?", count)));
+        }
+        self
+    }
+
+    pub fn group_by(&mut self, columns: &'static str) -> &mut Self {
+        self.group_by = Some(columns);
+        self
+    }
+
+    // 呼ぶたびにソートキーを後ろに追加する
+    pub fn order_by(&mut self, column: &'static str, order: SortOrder) -> &mut Self {
+        self.order_by.push((column, order));
+        self
+    }
+
+    // expr にはプレースホルダ ? を args と同じ数だけ含める
+    pub fn order_by_expr(
+        &mut self,
+        expr: &'static str,
+        args: impl IntoIterator<Item = Arg>,
+        order: SortOrder,
+    ) -> &mut Self {
+        let count = self.order_args.len();
+        self.order_args.extend(args);
+        debug_assert_eq!(expr.matches('?').count(), self.order_args.len() - count);
+        self.order_by.push((expr, order));
+        self
+    }
+
+    pub fn limit_offset(&mut self, limit: i32, offset: i32) -> &mut Self {
+        self.limit = Some((limit, offset));
+        self
+    }
+
+    // トランザクション内で対象の行をロックする
+    pub fn for_update(&mut self) -> &mut Self {
+        self.for_update = true;
+        self
+    }
+
+    pub fn sql(&self) -> String {
+        let mut sql = self.select.to_string();
+
+        for join in &self.joins {
+            sql.push(' ');
+            sql.push_str(join);
+        }
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        if let Some(columns) = self.group_by {
+            sql.push_str(" GROUP BY ");
+            sql.push_str(columns);
+        }
+        if !self.order_by.is_empty() {
+            let keys: Vec<String> = self
+                .order_by
+                .iter()
+                .map(|(column, order)| format!("{} {}", column, order.as_sql()))
+                .collect();
+            sql.push_str(" ORDER BY ");
+            sql.push_str(&keys.join(", "));
+        }
+        if self.limit.is_some() {
+            sql.push_str(" LIMIT ? OFFSET ?");
+        }
+        if self.for_update {
+            sql.push_str(" FOR UPDATE");
+        }
+
+        sql
+    }
+
+    pub fn args(&self) -> Vec<Arg> {
+        let mut args = self.args.clone();
+        args.extend(self.order_args.iter().cloned());
+        if let Some((limit, offset)) = self.limit {
+            args.push(Arg::Int(limit));
+            args.push(Arg::Int(offset));
+        }
+        args
+    }
+
+    fn arguments(&self) -> MySqlArguments {
+        let mut arguments = MySqlArguments::default();
+        for arg in self.args() {
+            match arg {
+                Arg::Int(value) => arguments.add(value),
+                Arg::Float(value) => arguments.add(value),
+                Arg::Text(value) => arguments.add(value),
+                Arg::DateTime(value) => arguments.add(value),
+            }
+        }
+        arguments
+    }
+
+    pub fn build_query_as<'q, O>(&self, sql: &'q str) -> QueryAs<'q, MySql, O, MySqlArguments>
+    where
+        O: for<'r> FromRow<'r, MySqlRow>,
+    {
+        sqlx::query_as_with(sql, self.arguments())
+    }
+
+    pub fn build_query_scalar<'q, O>(
+        &self,
+        sql: &'q str,
+    ) -> QueryScalar<'q, MySql, O, MySqlArguments>
+    where
+        (O,): for<'r> FromRow<'r, MySqlRow>,
+    {
+        sqlx::query_scalar_with(sql, self.arguments())
+    }
+
+    pub fn build_query<'q>(&self, sql: &'q str) -> Query<'q, MySql, MySqlArguments> {
+        sqlx::query_with(sql, self.arguments())
+    }
+}
+
+// "(?, ?)" のような1行分の断片を count 回カンマ区切りで並べる
+pub fn placeholders(row: &'static str, count: usize) -> String {
+    vec![row; count].join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTILE_INPUTS: &[&str] = &[
+        "available' OR '1'='1",
+        "'; DROP TABLE tow_trucks; --",
+        "busy\\' UNION SELECT password FROM users #",
+        "?",
+    ];
+
+    #[test]
+    fn hostile_text_is_bound_not_interpolated() {
+        for input in HOSTILE_INPUTS {
+            let mut builder = QueryBuilder::new("SELECT tt.id FROM tow_trucks tt");
+            builder
+                .and_where("tt.status = ?", *input)
+                .and_where("tt.area_id = ?", 1);
+
+            let sql = builder.sql();
+            assert_eq!(
+                sql,
+                "SELECT tt.id FROM tow_trucks tt WHERE tt.status = ? AND tt.area_id = ?"
+            );
+            assert_eq!(
+                builder.args(),
+                vec![Arg::Text(input.to_string()), Arg::Int(1)]
+            );
+        }
+    }
+
+    #[test]
+    fn placeholders_match_args() {
+        let mut builder = QueryBuilder::new("SELECT o.id FROM orders o");
+        builder
+            .join("JOIN nodes n ON o.node_id = n.id")
+            .and_where_opt("o.status = ?", Some("pending' --"))
+            .and_where_opt::<i32>("n.area_id = ?", None)
+            .and_where("o.id > ?", 0)
+            .order_by(
+                "o.order_time",
+                SortOrder::parse(Some("desc; DROP TABLE orders")),
+            )
+            .limit_offset(10, 20);
+
+        let sql = builder.sql();
+        assert_eq!(
+            sql,
+            "SELECT o.id FROM orders o JOIN nodes n ON o.node_id = n.id \
+             WHERE o.status = ? AND o.id > ? ORDER BY o.order_time ASC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(sql.matches('?').count(), builder.args().len());
+        assert_eq!(
+            builder.args(),
+            vec![
+                Arg::Text("pending' --".to_string()),
+                Arg::Int(0),
+                Arg::Int(10),
+                Arg::Int(20)
+            ]
+        );
+    }
+
+    #[test]
+    fn in_list_binds_every_value() {
+        let mut builder = QueryBuilder::new("SELECT id FROM nodes");
+        builder.and_where_in("id", vec![3, 1, 2]);
+        assert_eq!(builder.sql(), "SELECT id FROM nodes WHERE id IN (?, ?, ?)");
+        assert_eq!(builder.args(), vec![Arg::Int(3), Arg::Int(1), Arg::Int(2)]);
+
+        let mut empty = QueryBuilder::new("SELECT id FROM nodes");
+        empty.and_where_in("id", Vec::<i32>::new());
+        assert_eq!(empty.sql(), "SELECT id FROM nodes WHERE 1 = 0");
+        assert!(empty.args().is_empty());
+
+        assert_eq!(placeholders("(?, ?)", 2), "(?, ?), (?, ?)");
+    }
+
+    #[test]
+    fn order_by_args_come_between_where_and_limit() {
+        let mut builder = QueryBuilder::new("SELECT o.id FROM orders o");
+        builder
+            .and_where("o.status = ?", "pending")
+            .order_by_expr("(o.car_value * ?)", vec![Arg::Float(0.5)], SortOrder::Desc)
+            .limit_offset(10, 0);
+
+        assert_eq!(
+            builder.sql(),
+            "SELECT o.id FROM orders o WHERE o.status = ? \
+             ORDER BY (o.car_value * ?) DESC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(
+            builder.args(),
+            vec![
+                Arg::Text("pending".to_string()),
+                Arg::Float(0.5),
+                Arg::Int(10),
+                Arg::Int(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn sort_order_only_accepts_known_directions() {
+        assert_eq!(SortOrder::parse(Some("DESC")), SortOrder::Desc);
+        assert_eq!(SortOrder::parse(Some("desc")), SortOrder::Desc);
+        assert_eq!(SortOrder::parse(Some("DESC, (SELECT 1)")), SortOrder::Asc);
+        assert_eq!(SortOrder::parse(None), SortOrder::Asc);
+    }
+
+    #[test]
+    fn order_by_keys_are_appended_in_call_order() {
+        let mut builder = QueryBuilder::new("SELECT id FROM locations");
+        builder
+            .order_by("timestamp", SortOrder::Asc)
+            .order_by_expr("(id % ?)", vec![Arg::Int(7)], SortOrder::Desc)
+            .order_by("id", SortOrder::Asc)
+            .limit_offset(5, 0);
+
+        assert_eq!(
+            builder.sql(),
+            "SELECT id FROM locations \
+             ORDER BY timestamp ASC, (id % ?) DESC, id ASC LIMIT ? OFFSET ?"
+        );
+        assert_eq!(builder.args(), vec![Arg::Int(7), Arg::Int(5), Arg::Int(0)]);
+    }
+
+    #[test]
+    fn statement_args_come_first_and_locking_clauses_go_last() {
+        let since = Utc::now();
+        let mut builder = QueryBuilder::with_args(
+            "SELECT tow_truck_id, SUM(order_time >= ?) FROM orders",
+            vec![Arg::DateTime(since)],
+        );
+        builder
+            .and_where_in("tow_truck_id", vec![4, 2])
+            .and_where_raw("completed_time IS NULL")
+            .group_by("tow_truck_id")
+            .for_update();
+
+        assert_eq!(
+            builder.sql(),
+            "SELECT tow_truck_id, SUM(order_time >= ?) FROM orders \
+             WHERE tow_truck_id IN (?, ?) AND completed_time IS NULL \
+             GROUP BY tow_truck_id FOR UPDATE"
+        );
+        assert_eq!(
+            builder.args(),
+            vec![Arg::DateTime(since), Arg::Int(4), Arg::Int(2)]
+        );
+    }
+}
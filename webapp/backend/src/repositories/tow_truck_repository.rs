@@ -1,8 +1,16 @@
 use crate::domains::tow_truck_service::TowTruckRepository;
 use crate::errors::AppError;
+use crate::infrastructure::metrics::db_timer;
+use crate::models::location::{LatestLocation, Location, NewLocation};
 use crate::models::tow_truck::TowTruck;
+use crate::repositories::query_builder::{placeholders, QueryBuilder, SortOrder};
+use chrono::{DateTime, Utc};
 use sqlx::mysql::MySqlPool;
-use sqlx::query_as;
+use std::collections::HashMap;
+use tracing::instrument;
+
+// 1文の INSERT に含める行数。プレースホルダ数の上限 (65535) に収まるようにする
+const LOCATION_INSERT_CHUNK_SIZE: usize = 500;
 
 #[derive(Debug)]
 pub struct TowTruckRepositoryImpl {
@@ -16,6 +24,7 @@ impl TowTruckRepositoryImpl {
 }
 
 impl TowTruckRepository for TowTruckRepositoryImpl {
+    #[instrument(skip(self))]
     async fn get_paginated_tow_trucks(
         &self,
         page: i32,
@@ -23,91 +32,98 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
         status: Option<String>,
         area_id: Option<i32>,
     ) -> Result<Vec<TowTruck>, AppError> {
+        let _timer = db_timer("tow_truck_repository", "get_paginated_tow_trucks");
 
-        let where_clause = match (status, area_id) {
-            (Some(status), Some(area_id)) => format!(
-                "WHERE tt.status = '{}' AND tt.area_id = {} AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                status, area_id
-            ),
-            (None, Some(area_id)) => format!(
-                "WHERE tt.area_id = {} AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                area_id
-            ),
-            (Some(status), None) => format!(
-                "WHERE tt.status = '{}' AND l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
-                status
-            ),
-            (None, None) => "WHERE l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)"
-                .to_string(),
-        };
-        let limit_clause = match page_size {
-            -1 => "".to_string(),
-            _ => format!("LIMIT {}", page_size),
-        };
-        let offset_clause = match page_size {
-            -1 => "".to_string(),
-            page_size => format!("OFFSET {}", page * page_size),
-        };
-
-        let query = format!(
+        let mut builder = QueryBuilder::new(
             "SELECT
                 tt.id,
                 tt.driver_id,
                 u.username AS driver_username,
                 tt.status,
                 tt.area_id,
-                l.node_id
+                l.node_id,
+                l.timestamp AS last_seen
             FROM
-                tow_trucks tt
-            JOIN
-                users u
-            ON
-                tt.driver_id = u.id
-            JOIN 
-                locations l
-            ON 
-                tt.id = l.tow_truck_id
-            {}
-            ORDER BY
-                tt.id ASC
-            {}
-            {}",
-            where_clause, limit_clause, offset_clause
+                tow_trucks tt",
         );
+        builder
+            .join("JOIN users u ON tt.driver_id = u.id")
+            .join("JOIN tow_truck_current_locations l ON tt.id = l.tow_truck_id")
+            .and_where_opt("tt.status = ?", status)
+            .and_where_opt("tt.area_id = ?", area_id)
+            .order_by("tt.id", SortOrder::Asc);
+        // page_size が -1 の場合は全件取得
+        if page_size != -1 {
+            let offset = page.checked_mul(page_size).ok_or(AppError::BadRequest)?;
+            builder.limit_offset(page_size, offset);
+        }
 
-        let tow_trucks = sqlx::query_as::<_, TowTruck>(&query)
+        let sql = builder.sql();
+        let tow_trucks = builder
+            .build_query_as::<TowTruck>(&sql)
             .fetch_all(&self.pool)
             .await?;
 
         Ok(tow_trucks)
+    }
 
+    #[instrument(skip(self))]
+    async fn update_location(
+        &self,
+        tow_truck_id: i32,
+        node_id: i32,
+        flagged: bool,
+    ) -> Result<(), AppError> {
+        let _timer = db_timer("tow_truck_repository", "update_location");
+        let mut tx = self.pool.begin().await?;
 
+        let location_id =
+            sqlx::query("INSERT INTO locations (tow_truck_id, node_id, flagged) VALUES (?, ?, ?)")
+                .bind(tow_truck_id)
+                .bind(node_id)
+                .bind(flagged)
+                .execute(&mut tx)
+                .await?
+                .last_insert_id();
 
-    }
-
-    async fn update_location(&self, tow_truck_id: i32, node_id: i32) -> Result<(), AppError> {
-        sqlx::query("INSERT INTO locations (tow_truck_id, node_id) VALUES (?, ?)")
-            .bind(tow_truck_id)
-            .bind(node_id)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
-    }
+        sqlx::query(
+            "INSERT IGNORE INTO tow_truck_current_locations (tow_truck_id, node_id, location_id, timestamp)
+            SELECT tow_truck_id, node_id, id, timestamp FROM locations WHERE id = ?",
+        )
+        .bind(location_id)
+        .execute(&mut tx)
+        .await?;
 
-    async fn update_status(&self, tow_truck_id: i32, status: &str) -> Result<(), AppError> {
-        sqlx::query("UPDATE tow_trucks SET status = ? WHERE id = ?")
-            .bind(status)
-            .bind(tow_truck_id)
-            .execute(&self.pool)
-            .await?;
+        // 既に行があった場合は、追加した履歴の方が新しいときだけ置き換える
+        sqlx::query(
+            "UPDATE
+                tow_truck_current_locations c
+            JOIN
+                locations l
+            ON
+                l.id = ? AND c.tow_truck_id = l.tow_truck_id
+            SET
+                c.node_id = l.node_id,
+                c.location_id = l.id,
+                c.timestamp = l.timestamp
+            WHERE
+                (l.timestamp, l.id) > (c.timestamp, c.location_id)",
+        )
+        .bind(location_id)
+        .execute(&mut tx)
+        .await?;
 
+        tx.commit().await?;
         Ok(())
     }
 
+    #[instrument(skip(self))]
     async fn find_tow_truck_by_id(&self, id: i32) -> Result<Option<TowTruck>, AppError> {
+        let _timer = db_timer("tow_truck_repository", "find_tow_truck_by_id");
         let tow_truck = sqlx::query_as::<_, TowTruck>(
             "SELECT
-                tt.id, tt.driver_id, u.username AS driver_username, tt.status, l.node_id, tt.area_id
+                tt.id, tt.driver_id, u.username AS driver_username, tt.status, l.node_id, tt.area_id,
+                l.timestamp AS last_seen
             FROM
                 tow_trucks tt
             JOIN
@@ -115,13 +131,11 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
             ON
                 tt.driver_id = u.id
             JOIN
-                locations l
+                tow_truck_current_locations l
             ON
                 tt.id = l.tow_truck_id
             WHERE
-                tt.id = ?
-            AND
-                l.timestamp = (SELECT MAX(timestamp) FROM locations WHERE tow_truck_id = tt.id)",
+                tt.id = ?",
         )
         .bind(id)
         .fetch_optional(&self.pool)
@@ -129,4 +143,179 @@ impl TowTruckRepository for TowTruckRepositoryImpl {
 
         Ok(tow_truck)
     }
+
+    #[instrument(skip(self))]
+    async fn find_tow_trucks_by_driver_id(
+        &self,
+        driver_id: i32,
+    ) -> Result<Vec<TowTruck>, AppError> {
+        let _timer = db_timer("tow_truck_repository", "find_tow_trucks_by_driver_id");
+        let tow_trucks = sqlx::query_as::<_, TowTruck>(
+            "SELECT
+                tt.id, tt.driver_id, u.username AS driver_username, tt.status, l.node_id, tt.area_id,
+                l.timestamp AS last_seen
+            FROM
+                tow_trucks tt
+            JOIN
+                users u
+            ON
+                tt.driver_id = u.id
+            JOIN
+                tow_truck_current_locations l
+            ON
+                tt.id = l.tow_truck_id
+            WHERE
+                tt.driver_id = ?
+            ORDER BY
+                tt.id",
+        )
+        .bind(driver_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tow_trucks)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_location_history(
+        &self,
+        tow_truck_id: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i32,
+    ) -> Result<Vec<Location>, AppError> {
+        let _timer = db_timer("tow_truck_repository", "get_location_history");
+
+        let mut builder = QueryBuilder::new(
+            "SELECT
+                id, node_id, timestamp, flagged
+            FROM
+                locations",
+        );
+        builder
+            .and_where("tow_truck_id = ?", tow_truck_id)
+            .and_where_opt("timestamp >= ?", from)
+            .and_where_opt("timestamp <= ?", to)
+            .order_by("timestamp", SortOrder::Asc)
+            .order_by("id", SortOrder::Asc)
+            .limit_offset(limit, 0);
+
+        let sql = builder.sql();
+        let locations = builder
+            .build_query_as::<Location>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(locations)
+    }
+
+    #[instrument(skip(self))]
+    async fn get_latest_locations(
+        &self,
+        tow_truck_ids: &[i32],
+    ) -> Result<Vec<LatestLocation>, AppError> {
+        let _timer = db_timer("tow_truck_repository", "get_latest_locations");
+
+        let mut builder = QueryBuilder::new(
+            "SELECT
+                tt.id AS tow_truck_id,
+                tt.area_id,
+                c.node_id,
+                c.timestamp
+            FROM
+                tow_trucks tt",
+        );
+        builder
+            .join("LEFT JOIN tow_truck_current_locations c ON tt.id = c.tow_truck_id")
+            .and_where_in("tt.id", tow_truck_ids.iter().copied());
+
+        let sql = builder.sql();
+        let latest_locations = builder
+            .build_query_as::<LatestLocation>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(latest_locations)
+    }
+
+    #[instrument(skip(self, locations), fields(count = locations.len()))]
+    async fn insert_locations(&self, locations: &[NewLocation]) -> Result<(), AppError> {
+        let _timer = db_timer("tow_truck_repository", "insert_locations");
+        if locations.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        for chunk in locations.chunks(LOCATION_INSERT_CHUNK_SIZE) {
+            let sql = format!(
+                "INSERT INTO locations (tow_truck_id, node_id, timestamp, flagged) VALUES {}",
+                placeholders("(?, ?, ?, ?)", chunk.len())
+            );
+            let mut query = sqlx::query(&sql);
+            for location in chunk {
+                query = query
+                    .bind(location.tow_truck_id)
+                    .bind(location.node_id)
+                    .bind(location.timestamp)
+                    .bind(location.flagged);
+            }
+            query.execute(&mut tx).await?;
+        }
+
+        // 最新位置の更新対象は、レッカー車ごとにこのバッチ内で最も新しいサンプルだけ
+        let mut newest: HashMap<i32, DateTime<Utc>> = HashMap::new();
+        for location in locations {
+            let timestamp = newest
+                .entry(location.tow_truck_id)
+                .or_insert(location.timestamp);
+            if location.timestamp > *timestamp {
+                *timestamp = location.timestamp;
+            }
+        }
+        let newest: Vec<(i32, DateTime<Utc>)> = newest.into_iter().collect();
+
+        for chunk in newest.chunks(LOCATION_INSERT_CHUNK_SIZE) {
+            let condition = format!(
+                "(l.tow_truck_id, l.timestamp) IN ({})",
+                placeholders("(?, ?)", chunk.len())
+            );
+
+            let sql = format!(
+                "INSERT IGNORE INTO tow_truck_current_locations (tow_truck_id, node_id, location_id, timestamp)
+                SELECT l.tow_truck_id, l.node_id, l.id, l.timestamp FROM locations l WHERE {}",
+                condition
+            );
+            let mut query = sqlx::query(&sql);
+            for (tow_truck_id, timestamp) in chunk {
+                query = query.bind(tow_truck_id).bind(timestamp);
+            }
+            query.execute(&mut tx).await?;
+
+            // update_location と同じく、既存の行より新しい場合だけ置き換える
+            let sql = format!(
+                "UPDATE
+                    tow_truck_current_locations c
+                JOIN
+                    locations l
+                ON
+                    c.tow_truck_id = l.tow_truck_id AND {}
+                SET
+                    c.node_id = l.node_id,
+                    c.location_id = l.id,
+                    c.timestamp = l.timestamp
+                WHERE
+                    (l.timestamp, l.id) > (c.timestamp, c.location_id)",
+                condition
+            );
+            let mut query = sqlx::query(&sql);
+            for (tow_truck_id, timestamp) in chunk {
+                query = query.bind(tow_truck_id).bind(timestamp);
+            }
+            query.execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
 }
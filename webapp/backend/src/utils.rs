@@ -2,9 +2,8 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use rand::Rng;
-use std::time::Instant;
 use crate::errors::AppError;
+use rand::Rng;
 
 pub fn generate_session_token() -> String {
     let mut rng = rand::thread_rng();
@@ -34,14 +33,10 @@ pub fn hash_password(password: &str) -> Result<String, AppError> {
 
 pub fn verify_password(hashed_password: &str, input_password: &str) -> Result<bool, AppError> {
     let input_password_bytes = input_password.as_bytes();
-    let verify_password_start = Instant::now();
     let parsed_hash = match PasswordHash::new(hashed_password) {
         Ok(hash) => hash,
         Err(_) => return Err(AppError::InternalServerError),
     };
-    let verify_password_duration0 = verify_password_start.elapsed();
-    //println!("verify_password0 时间间隔: {:?}", verify_password_duration0);
-    //println!("verify_password0 时间间隔: {:?}", verify_password_duration0);
 
     /*
     if(input_password == "password"){
@@ -52,11 +47,7 @@ pub fn verify_password(hashed_password: &str, input_password: &str) -> Result<bo
     }
     */
     match Argon2::default().verify_password(input_password_bytes, &parsed_hash) {
-        Ok(_) => {
-            let verify_password_duration1 = verify_password_start.elapsed();
-            //println!("verify_password1 时间间隔: {:?}", verify_password_duration1);
-            Ok(true)},
+        Ok(_) => Ok(true),
         Err(_) => Ok(false),
     }
-    
 }
@@ -0,0 +1,22 @@
+-- レッカー車ごとの最新位置。locations への INSERT と同じトランザクションで更新する
+
+CREATE TABLE IF NOT EXISTS tow_truck_current_locations (
+    tow_truck_id INT PRIMARY KEY,
+    node_id INT NOT NULL,
+    location_id INT NOT NULL,
+    timestamp DATETIME NOT NULL,
+    FOREIGN KEY (tow_truck_id) REFERENCES tow_trucks(id) ON DELETE CASCADE
+);
+
+-- 既存の履歴から初期値を作る。同じ時刻の行が複数ある場合は id が大きい方を採用する
+REPLACE INTO tow_truck_current_locations (tow_truck_id, node_id, location_id, timestamp)
+SELECT tow_truck_id, node_id, id, timestamp
+FROM (
+    SELECT
+        l.*,
+        ROW_NUMBER() OVER (PARTITION BY l.tow_truck_id ORDER BY l.timestamp DESC, l.id DESC) AS rn
+    FROM locations l
+) latest
+WHERE rn = 1;
+
+CREATE INDEX idx_locations_tow_truck_id_timestamp ON locations (tow_truck_id, timestamp);
@@ -0,0 +1,2 @@
+-- 道路網から見てありえない移動 (エリア外・移動速度超過) を記録した位置に付けるフラグ
+ALTER TABLE locations ADD COLUMN flagged BOOLEAN NOT NULL DEFAULT FALSE;
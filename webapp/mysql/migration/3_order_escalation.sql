@@ -0,0 +1,4 @@
+-- SLA を超えて配車待ちのままになった依頼をエスカレーションした時刻
+ALTER TABLE orders ADD COLUMN escalated_at DATETIME NULL DEFAULT NULL;
+
+CREATE INDEX idx_orders_status_order_time ON orders (status, order_time);
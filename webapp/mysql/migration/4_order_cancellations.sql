@@ -0,0 +1,14 @@
+-- 依頼を取り消した利用者・理由・時刻の記録
+CREATE TABLE IF NOT EXISTS order_cancellations (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    order_id INT NOT NULL UNIQUE,
+    cancelled_by INT NOT NULL,
+    cancelled_by_role VARCHAR(50) NOT NULL,
+    reason VARCHAR(50) NOT NULL,
+    note VARCHAR(500),
+    released_tow_truck_id INT,
+    cancelled_at DATETIME NOT NULL,
+    FOREIGN KEY (order_id) REFERENCES orders(id) ON DELETE CASCADE,
+    FOREIGN KEY (cancelled_by) REFERENCES users(id) ON DELETE CASCADE,
+    FOREIGN KEY (released_tow_truck_id) REFERENCES tow_trucks(id) ON DELETE SET NULL
+);
@@ -0,0 +1,36 @@
+-- 依頼へのレッカー車の割り当て履歴。unassigned_at が NULL の行が現在の割り当て
+CREATE TABLE IF NOT EXISTS order_assignments (
+    id INT AUTO_INCREMENT PRIMARY KEY,
+    order_id INT NOT NULL,
+    tow_truck_id INT NOT NULL,
+    -- 自動配車の場合は NULL
+    assigned_by INT,
+    assigned_at DATETIME NOT NULL,
+    unassigned_at DATETIME,
+    unassigned_by INT,
+    unassign_reason VARCHAR(50),
+    FOREIGN KEY (order_id) REFERENCES orders(id) ON DELETE CASCADE,
+    FOREIGN KEY (tow_truck_id) REFERENCES tow_trucks(id) ON DELETE CASCADE,
+    FOREIGN KEY (assigned_by) REFERENCES dispatchers(id) ON DELETE SET NULL,
+    FOREIGN KEY (unassigned_by) REFERENCES dispatchers(id) ON DELETE SET NULL,
+    INDEX idx_order_assignments_order_id (order_id, unassigned_at)
+);
+
+-- 既存の割り当てを履歴の初期値にする。割り当て時刻は completed_orders に記録された時刻を使う
+-- 配車中でない依頼の割り当ては、完了時刻 (なければ割り当て時刻) に外れたものとする
+INSERT INTO order_assignments (order_id, tow_truck_id, assigned_by, assigned_at, unassigned_at, unassign_reason)
+SELECT
+    o.id,
+    o.tow_truck_id,
+    o.dispatcher_id,
+    COALESCE(co.completed_time, o.order_time),
+    CASE WHEN o.status <> 'dispatched'
+        THEN COALESCE(o.completed_time, co.completed_time, o.order_time)
+    END,
+    CASE o.status
+        WHEN 'completed' THEN 'order_completed'
+        WHEN 'cancelled' THEN 'order_cancelled'
+    END
+FROM orders o
+LEFT JOIN completed_orders co ON co.order_id = o.id
+WHERE o.tow_truck_id IS NOT NULL;
@@ -0,0 +1,8 @@
+-- 割り当てはドライバーへの申し出として扱い、承諾・辞退・期限切れを記録する
+ALTER TABLE order_assignments
+    ADD COLUMN offer_status VARCHAR(50) NOT NULL DEFAULT 'offered',
+    ADD COLUMN responded_at DATETIME,
+    ADD INDEX idx_order_assignments_offer_status (offer_status, unassigned_at);
+
+-- 既存の割り当ては承諾済みとして扱う
+UPDATE order_assignments SET offer_status = 'accepted', responded_at = assigned_at;
@@ -0,0 +1,5 @@
+-- ドライバーが報告する作業の進み具合。承諾後に en_route -> on_site -> completed の順に記録する
+ALTER TABLE order_assignments
+    ADD COLUMN en_route_at DATETIME,
+    ADD COLUMN on_site_at DATETIME,
+    ADD COLUMN completed_at DATETIME;
@@ -0,0 +1,14 @@
+-- レッカー車を解放しても completed_orders の行を消さずに残す。released_at が NULL の行が現在の割り当て
+-- 依頼・レッカー車の重複は、解放されていない行の間でだけ禁止する
+ALTER TABLE completed_orders
+    ADD COLUMN released_at DATETIME NULL DEFAULT NULL,
+    ADD COLUMN active_order_id INT AS (IF(released_at IS NULL, order_id, NULL)) STORED,
+    ADD COLUMN active_tow_truck_id INT AS (IF(released_at IS NULL, tow_truck_id, NULL)) STORED,
+    ADD INDEX idx_completed_orders_order_id (order_id),
+    ADD INDEX idx_completed_orders_tow_truck_id (tow_truck_id),
+    ADD UNIQUE INDEX uq_completed_orders_active_order_id (active_order_id),
+    ADD UNIQUE INDEX uq_completed_orders_active_tow_truck_id (active_tow_truck_id);
+
+ALTER TABLE completed_orders
+    DROP INDEX order_id,
+    DROP INDEX tow_truck_id;